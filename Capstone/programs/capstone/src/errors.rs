@@ -30,4 +30,76 @@ pub enum SolVeilErrors {
     InvalidPlan,
     #[msg("Already claimed")]
     AlreadyClaimed,
+    #[msg("Invalid tier count")]
+    InvalidTierCount,
+    #[msg("Invalid tier")]
+    InvalidTier,
+    #[msg("Subscription is already on this tier")]
+    SameTier,
+    #[msg("Invalid collection")]
+    InvalidCollection,
+    #[msg("Unvested balance below required threshold")]
+    InsufficientUnvestedBalance,
+    #[msg("Subscription expired")]
+    SubscriptionExpired,
+    #[msg("Subscription has not expired yet")]
+    SubscriptionNotExpired,
+    #[msg("Too many pending vesting tranches")]
+    TooManyTranches,
+    #[msg("Auto-renewal is not enabled")]
+    AutoRenewalDisabled,
+    #[msg("Invalid auto-renewal payment account")]
+    InvalidAutoRenewalAccount,
+    #[msg("Renewal is not due yet")]
+    RenewalNotDue,
+    #[msg("Auto-renewal spending cap exceeded")]
+    AutoRenewalCapExceeded,
+    #[msg("Payment accounts do not match the plan's payment mode")]
+    InvalidPaymentAccounts,
+    #[msg("Not supported for SOL plans")]
+    NativePlanUnsupported,
+    #[msg("Plan is paused")]
+    PlanPaused,
+    #[msg("Plan has been sunset")]
+    PlanSunset,
+    #[msg("Sunset time must not be in the past")]
+    InvalidSunsetTime,
+    #[msg("Plan still has active subscriptions or undistributed funds")]
+    PlanNotDrained,
+    #[msg("Protocol fee exceeds the maximum")]
+    InvalidProtocolFee,
+    #[msg("Fee destination is not the protocol treasury")]
+    InvalidTreasury,
+    #[msg("Invalid vesting schedule")]
+    InvalidVestingSchedule,
+    #[msg("Plan does not offer trials")]
+    TrialsDisabled,
+    #[msg("Trial must be converted first")]
+    TrialNotConverted,
+    #[msg("Subscription is not a trial")]
+    NotATrial,
+    #[msg("Subscription NFT still exists; close with it instead")]
+    NftNotLost,
+    #[msg("Recipient must sign on non-transferable plans")]
+    RecipientMustSign,
+    #[msg("Refund destination does not match the subscription")]
+    InvalidRefundDestination,
+    #[msg("Seat count must be greater than zero")]
+    InvalidSeatCount,
+    #[msg("Subscription is not a seat pool")]
+    NotASeatPool,
+    #[msg("All seats are assigned")]
+    NoSeatsAvailable,
+    #[msg("Revoke assigned seats first")]
+    SeatsStillAssigned,
+    #[msg("Payment account already funds another auto-renewal; disable it first")]
+    AutoRenewalTokenInUse,
+    #[msg("Subscription still holds unvested funds; the subscriber closes it for a refund")]
+    UnvestedFundsRemain,
+    #[msg("Seat pool NFTs do not grant access; assign a seat instead")]
+    SeatPoolNotASubscription,
+    #[msg("Non-transferable NFTs stay frozen in the subscriber's ATA; close instead")]
+    NftFrozen,
+    #[msg("Only the subscription's original buyer can recover a burned NFT")]
+    NotOriginalSubscriber,
 }
\ No newline at end of file
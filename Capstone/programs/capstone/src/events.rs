@@ -0,0 +1,151 @@
+use anchor_lang::prelude::*;
+
+#[event]
+pub struct PlanCreated {
+    pub plan: Pubkey,
+    pub creator: Pubkey,
+    /// `None` for plans priced in SOL
+    pub payment_mint: Option<Pubkey>,
+    pub nft_collection: Pubkey,
+    pub tier_count: u8,
+    pub transferable: bool,
+    pub grace_period: u64,
+}
+
+#[event]
+pub struct SubscriptionPurchased {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub subscriber: Pubkey,
+    pub nft_mint: Pubkey,
+    pub tier: u8,
+    /// Total recorded for the subscription: upfront plus what reached the vault
+    pub deposit: u64,
+    pub upfront: u64,
+    pub protocol_fee: u64,
+    pub expires_at: u64,
+    /// Seats in the pool; 0 for an individual subscription
+    pub seats: u16,
+}
+
+#[event]
+pub struct SubscriptionGifted {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub payer: Pubkey,
+    pub recipient: Pubkey,
+    /// `None` when refunds go to the recipient
+    pub refund_destination: Option<Pubkey>,
+}
+
+#[event]
+pub struct TrialStarted {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub subscriber: Pubkey,
+    pub nft_mint: Pubkey,
+    pub tier: u8,
+    pub expires_at: u64,
+}
+
+#[event]
+pub struct Renewed {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    /// Amount that reached the vault
+    pub amount: u64,
+    pub expires_at: u64,
+    /// Pulled through the auto-renewal delegate rather than paid by the subscriber
+    pub auto_renewal: bool,
+}
+
+#[event]
+pub struct CreatorClaimed {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub vested: u64,
+    /// Newly claimed from this subscription, before the protocol fee
+    pub amount: u64,
+    pub claimed_total: u64,
+}
+
+#[event]
+pub struct SubscriptionClosed {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub subscriber: Pubkey,
+    pub vested: u64,
+    /// Vested but unclaimed funds paid out to the creator on close
+    pub paid_to_creator: u64,
+    pub refund: u64,
+}
+
+#[event]
+pub struct SubscriptionCancelled {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub subscriber: Pubkey,
+    pub vested: u64,
+    /// Vested but unclaimed funds settled to the creator
+    pub paid_to_creator: u64,
+    pub refund: u64,
+}
+
+#[event]
+pub struct TierChanged {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub subscriber: Pubkey,
+    pub old_tier: u8,
+    pub new_tier: u8,
+    pub vested: u64,
+    /// Unvested deposit carried over to the new tier
+    pub credit: u64,
+    /// Collected from the subscriber when the new tier costs more than the credit
+    pub received: u64,
+    /// New tier's upfront share, paid to the creator with the unclaimed vested funds
+    pub upfront: u64,
+    /// Leftover credit when the new tier costs less
+    pub refund: u64,
+}
+
+#[event]
+pub struct SubscriptionExpired {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub vested: u64,
+    /// Vested but unclaimed funds paid out to the creator on expiry
+    pub paid_to_creator: u64,
+}
+
+#[event]
+pub struct PlanStatusChanged {
+    pub plan: Pubkey,
+    pub paused: bool,
+    /// 0 while the plan is not sunset
+    pub sunset_at: u64,
+    /// Set by `close_plan`; the plan account is gone afterwards
+    pub closed: bool,
+}
+
+#[event]
+pub struct SeatAssigned {
+    pub plan: Pubkey,
+    /// The seat pool's UserSubscription
+    pub user_subscription: Pubkey,
+    pub seat: Pubkey,
+    pub member: Pubkey,
+    pub nft_mint: Pubkey,
+    pub assigned_seats: u16,
+}
+
+#[event]
+pub struct SeatRevoked {
+    pub plan: Pubkey,
+    pub user_subscription: Pubkey,
+    pub seat: Pubkey,
+    pub member: Pubkey,
+    /// False when the seat NFT could not be burned, e.g. it left the member's wallet
+    pub nft_burned: bool,
+    pub assigned_seats: u16,
+}
@@ -1,261 +1,528 @@
-use anchor_lang::prelude::*;
+use anchor_lang::{prelude::*, system_program};
 
 mod errors;
 use crate::errors::SolVeilErrors;
 
+pub mod events;
+use crate::events::*;
+
+pub mod vesting;
+use crate::vesting::{VestingSchedule, VestingTranche};
+
 use anchor_spl::{
     associated_token::AssociatedToken,
     metadata::{
-        create_master_edition_v3, create_metadata_accounts_v3, mpl_token_metadata::types::DataV2,
-        CreateMasterEditionV3, CreateMetadataAccountsV3,
+        create_master_edition_v3, create_metadata_accounts_v3, freeze_delegated_account,
+        mpl_token_metadata::types::{CollectionDetails, DataV2}, thaw_delegated_account,
+        update_metadata_accounts_v2, verify_sized_collection_item, CreateMasterEditionV3,
+        CreateMetadataAccountsV3, FreezeDelegatedAccount, ThawDelegatedAccount,
+        UpdateMetadataAccountsV2, VerifySizedCollectionItem,
     },
-    token::{self, Mint, Token, TokenAccount, Transfer, MintTo, Burn, CloseAccount},
+    token::{self, Approve, Mint, Token, TokenAccount, MintTo, Burn, CloseAccount},
+    token_interface::{
+        self, Mint as PaymentMint, TokenAccount as PaymentTokenAccount, TokenInterface,
+        TransferChecked,
+    },
+};
+use mpl_token_metadata::{
+    accounts::{MasterEdition, Metadata as MetadataAccount},
+    types::Collection,
 };
-use mpl_token_metadata::accounts::{MasterEdition, Metadata as MetadataAccount};
 
 declare_id!("FisvpEC1NDf4kZtzJY3cBvA6xJnohVxjD3WvzxJk5jRu");
 
+pub const MAX_TIERS: usize = 5;
+pub const MAX_URI_LEN: usize = 200;
+pub const MAX_TRANCHES: usize = 8;
+pub const AUTO_RENEWAL_WINDOW: u64 = 86_400;
+pub const MAX_PROTOCOL_FEE_BPS: u16 = 1_000;
+pub const CANCELLED_NAME_PREFIX: &str = "Cancelled: ";
+
 #[program]
 pub mod solvency {
     use super::*;
-    use mpl_token_metadata::types::Collection;
 
     pub fn create_plan(
         ctx: Context<CreatePlan>,
         plan_seed: String,
-        upfront_percentage: u8,
-        vesting_duration: u64,
-        nft_collection: Pubkey,
+        args: CreatePlanArgs,
     ) -> Result<()> {
+        let CreatePlanArgs {
+            tiers,
+            vesting_schedule,
+            cliff_duration,
+            trial_duration,
+            transferable,
+            grace_period,
+            collection_name,
+            collection_symbol,
+            collection_uri,
+        } = args;
         if plan_seed.len() > 32 {
             return err!(SolVeilErrors::PlanSeedTooLong);
         }
-        require!(upfront_percentage <= 100, SolVeilErrors::InvalidUpfrontPercentage);
-        
-        let plan = &mut ctx.accounts.plan;
-        plan.creator = ctx.accounts.creator.key();
-        plan.upfront_percentage = upfront_percentage;
-        plan.vesting_duration = vesting_duration;
-        plan.payment_mint = ctx.accounts.payment_mint.key();
-        plan.nft_collection = nft_collection;
-        plan.creation_timestamp = Clock::get()?.unix_timestamp as u64;
-        plan.bump = ctx.bumps.plan;
-        plan.seed = plan_seed.into_bytes();
-        Ok(())
-    }
-
-    pub fn buy_subscription(
-        ctx: Context<BuySubscription>,
-        amount: u64,
-        name: String,
-        symbol: String,
-        uri: String,
-    ) -> Result<()> {
-        let plan = &ctx.accounts.plan;
-        
-        require!(amount > 0, SolVeilErrors::InvalidAmount);
-        require!(name.len() <= 32, SolVeilErrors::NameTooLong);
-        require!(symbol.len() <= 10, SolVeilErrors::SymbolTooLong);
-        require!(uri.len() <= 200, SolVeilErrors::UriTooLong);
-
-        let upfront = ((plan.upfront_percentage as u128 * amount as u128) / 100) as u64;
-        let remaining = amount.saturating_sub(upfront);
-
-        // Transfer upfront to creator if any
-        if upfront > 0 {
-            token::transfer(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_token.to_account_info(),
-                        to: ctx.accounts.creator_token.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
-                    },
-                ),
-                upfront,
-            )?;
+        require!(collection_name.len() <= 32, SolVeilErrors::NameTooLong);
+        require!(collection_symbol.len() <= 10, SolVeilErrors::SymbolTooLong);
+        require!(collection_uri.len() <= MAX_URI_LEN, SolVeilErrors::UriTooLong);
+        require!(
+            !tiers.is_empty() && tiers.len() <= MAX_TIERS,
+            SolVeilErrors::InvalidTierCount
+        );
+        for tier in tiers.iter() {
+            require!(tier.price > 0, SolVeilErrors::InvalidAmount);
+            require!(tier.upfront_percentage <= 100, SolVeilErrors::InvalidUpfrontPercentage);
+            require!(tier.uri.len() <= MAX_URI_LEN, SolVeilErrors::UriTooLong);
         }
+        vesting_schedule.validate()?;
+        // A plan cliff would stack with a schedule's own per-tranche cliff
+        require!(
+            cliff_duration == 0 || vesting_schedule == VestingSchedule::Linear,
+            SolVeilErrors::InvalidVestingSchedule
+        );
 
-        // Transfer remaining to vault
-        if remaining > 0 {
-            token::transfer(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_token.to_account_info(),
-                        to: ctx.accounts.vault.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
-                    },
-                ),
-                remaining,
-            )?;
-        }
+        // Verify collection metadata and edition PDAs
+        let (metadata_pda, _) = MetadataAccount::find_pda(&ctx.accounts.collection_mint.key());
+        require_keys_eq!(metadata_pda, ctx.accounts.collection_metadata.key());
+        let (edition_pda, _) = MasterEdition::find_pda(&ctx.accounts.collection_mint.key());
+        require_keys_eq!(edition_pda, ctx.accounts.collection_master_edition.key());
 
-        // Verify metadata and edition PDAs
-        let (metadata_pda, _) = MetadataAccount::find_pda(&ctx.accounts.nft_mint.key());
-        require_keys_eq!(metadata_pda, ctx.accounts.metadata.key());
-        let (edition_pda, _) = MasterEdition::find_pda(&ctx.accounts.nft_mint.key());
-        require_keys_eq!(edition_pda, ctx.accounts.master_edition.key());
-
-        // Mint NFT
-        // Bind temporaries first
-        let plan_key = plan.key();  // Pubkey is Copy, so this is fine
-        let user_key = ctx.accounts.user.key();
-        let bump = [ctx.bumps.user_subscription];  // [u8; 1]
-
-        // Now create the owned array
-        let sub_seeds_inner_array = [
-            b"user_subscription" as &[u8],
-            plan_key.as_ref(),
-            user_key.as_ref(),
-            &bump,
+        // The plan PDA is mint and update authority of the collection NFT
+        let creator_key = ctx.accounts.creator.key();
+        let plan_seeds_inner: &[&[u8]] = &[
+            b"plan",
+            creator_key.as_ref(),
+            plan_seed.as_bytes(),
+            &[ctx.bumps.plan],
         ];
+        let plan_seeds: &[&[&[u8]]] = &[plan_seeds_inner];
 
-        // Slice it
-        let sub_seeds_inner: &[&[u8]] = &sub_seeds_inner_array;
-
-        // Wrap for CPI (multiple signers, even if one)
-        let sub_seeds: &[&[&[u8]]] = &[sub_seeds_inner];
-        
         token::mint_to(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 MintTo {
-                    mint: ctx.accounts.nft_mint.to_account_info(),
-                    to: ctx.accounts.nft_ata.to_account_info(),
-                    authority: ctx.accounts.user_subscription.to_account_info(),
+                    mint: ctx.accounts.collection_mint.to_account_info(),
+                    to: ctx.accounts.collection_token.to_account_info(),
+                    authority: ctx.accounts.plan.to_account_info(),
                 },
-                sub_seeds,
+                plan_seeds,
             ),
             1,
         )?;
 
-        // Create metadata
-        let data_v2 = DataV2 {
-            name,
-            symbol,
-            uri,
-            seller_fee_basis_points: 0,
-            creators: None,
-            collection: Some(Collection {
-                verified: false,
-                key: ctx.accounts.plan.nft_collection,
-            }),
-            uses: None,
-        };
-        
         create_metadata_accounts_v3(
             CpiContext::new_with_signer(
                 ctx.accounts.token_metadata_program.to_account_info(),
                 CreateMetadataAccountsV3 {
-                    metadata: ctx.accounts.metadata.to_account_info(),
-                    mint: ctx.accounts.nft_mint.to_account_info(),
-                    mint_authority: ctx.accounts.user_subscription.to_account_info(),
-                    payer: ctx.accounts.user.to_account_info(),
-                    update_authority: ctx.accounts.user_subscription.to_account_info(),
+                    metadata: ctx.accounts.collection_metadata.to_account_info(),
+                    mint: ctx.accounts.collection_mint.to_account_info(),
+                    mint_authority: ctx.accounts.plan.to_account_info(),
+                    payer: ctx.accounts.creator.to_account_info(),
+                    update_authority: ctx.accounts.plan.to_account_info(),
                     system_program: ctx.accounts.system_program.to_account_info(),
                     rent: ctx.accounts.rent.to_account_info(),
                 },
-                sub_seeds,
+                plan_seeds,
             ),
-            data_v2,
+            DataV2 {
+                name: collection_name,
+                symbol: collection_symbol,
+                uri: collection_uri,
+                seller_fee_basis_points: 0,
+                creators: None,
+                collection: None,
+                uses: None,
+            },
             true,
             true,
-            None,
+            Some(CollectionDetails::V1 { size: 0 }),
         )?;
 
-        // Create master edition
         create_master_edition_v3(
             CpiContext::new_with_signer(
                 ctx.accounts.token_metadata_program.to_account_info(),
                 CreateMasterEditionV3 {
-                    edition: ctx.accounts.master_edition.to_account_info(),
-                    mint: ctx.accounts.nft_mint.to_account_info(),
-                    update_authority: ctx.accounts.user_subscription.to_account_info(),
-                    mint_authority: ctx.accounts.user_subscription.to_account_info(),
-                    payer: ctx.accounts.user.to_account_info(),
-                    metadata: ctx.accounts.metadata.to_account_info(),
+                    edition: ctx.accounts.collection_master_edition.to_account_info(),
+                    mint: ctx.accounts.collection_mint.to_account_info(),
+                    update_authority: ctx.accounts.plan.to_account_info(),
+                    mint_authority: ctx.accounts.plan.to_account_info(),
+                    payer: ctx.accounts.creator.to_account_info(),
+                    metadata: ctx.accounts.collection_metadata.to_account_info(),
                     token_program: ctx.accounts.token_program.to_account_info(),
                     system_program: ctx.accounts.system_program.to_account_info(),
                     rent: ctx.accounts.rent.to_account_info(),
                 },
-                sub_seeds,
+                plan_seeds,
             ),
             Some(0),
         )?;
 
-        // Initialize user subscription
+        // Token plans use the vault initialized above; SOL plans seed the system-owned vault
+        // with its rent-exempt minimum so deposits of any size can land in it
+        let vault_bump = match (
+            &ctx.accounts.payment_mint,
+            &ctx.accounts.vault,
+            &ctx.accounts.sol_vault,
+        ) {
+            (Some(_), Some(_), None) => ctx.bumps.vault,
+            (None, None, Some(sol_vault)) => {
+                system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        system_program::Transfer {
+                            from: ctx.accounts.creator.to_account_info(),
+                            to: sol_vault.to_account_info(),
+                        },
+                    ),
+                    Rent::get()?.minimum_balance(0),
+                )?;
+                ctx.bumps.sol_vault
+            }
+            _ => None,
+        }
+        .ok_or(error!(SolVeilErrors::InvalidPaymentAccounts))?;
+
+        let plan = &mut ctx.accounts.plan;
+        plan.creator = ctx.accounts.creator.key();
+        plan.payment_mint = ctx.accounts.payment_mint.as_ref().map(|mint| mint.key());
+        plan.nft_collection = ctx.accounts.collection_mint.key();
+        plan.transferable = transferable;
+        plan.grace_period = grace_period;
+        plan.creation_timestamp = Clock::get()?.unix_timestamp as u64;
+        plan.bump = ctx.bumps.plan;
+        plan.vault_bump = vault_bump;
+        plan.seed = plan_seed.into_bytes();
+        plan.tiers = tiers;
+        plan.vesting_schedule = vesting_schedule;
+        plan.cliff_duration = cliff_duration;
+        plan.trial_duration = trial_duration;
+        ctx.accounts.config.bump = ctx.bumps.config;
+
+        emit!(PlanCreated {
+            plan: plan.key(),
+            creator: plan.creator,
+            payment_mint: plan.payment_mint,
+            nft_collection: plan.nft_collection,
+            tier_count: plan.tiers.len() as u8,
+            transferable,
+            grace_period,
+        });
+        Ok(())
+    }
+
+    pub fn buy_subscription(
+        ctx: Context<BuySubscription>,
+        tier_index: u8,
+        name: String,
+        symbol: String,
+    ) -> Result<()> {
+        purchase(
+            ctx.accounts.purchase_accounts(&ctx.bumps),
+            tier_index,
+            0,
+            name,
+            symbol,
+            None,
+        )
+    }
+
+    /// Buys `seats` seats of a tier in one payment of `price * seats`. The buyer's subscription
+    /// becomes the seat pool: its NFT is the admin record used to assign and revoke seats.
+    pub fn buy_seats(
+        ctx: Context<BuySubscription>,
+        tier_index: u8,
+        seats: u16,
+        name: String,
+        symbol: String,
+    ) -> Result<()> {
+        require!(seats > 0, SolVeilErrors::InvalidSeatCount);
+        purchase(
+            ctx.accounts.purchase_accounts(&ctx.bumps),
+            tier_index,
+            seats,
+            name,
+            symbol,
+            None,
+        )
+    }
+
+    /// Buys a subscription on behalf of `recipient`, who owns the NFT and the subscription.
+    /// With `refund_to_payer` refunds on close go back to the payer instead of the recipient.
+    /// On non-transferable plans the recipient co-signs so the NFT can be frozen in their wallet.
+    pub fn gift_subscription(
+        ctx: Context<GiftSubscription>,
+        tier_index: u8,
+        name: String,
+        symbol: String,
+        refund_to_payer: bool,
+    ) -> Result<()> {
+        let refund_destination = refund_to_payer.then(|| ctx.accounts.payer.key());
+        purchase(
+            ctx.accounts.purchase_accounts(&ctx.bumps),
+            tier_index,
+            0,
+            name,
+            symbol,
+            refund_destination,
+        )?;
+
+        emit!(SubscriptionGifted {
+            plan: ctx.accounts.plan.key(),
+            user_subscription: ctx.accounts.user_subscription.key(),
+            payer: ctx.accounts.payer.key(),
+            recipient: ctx.accounts.recipient.key(),
+            refund_destination,
+        });
+        Ok(())
+    }
+
+    /// Mints the subscription NFT without payment; access lasts the plan's trial duration.
+    pub fn start_trial(
+        ctx: Context<StartTrial>,
+        tier_index: u8,
+        name: String,
+        symbol: String,
+    ) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        require!(plan.trial_duration > 0, SolVeilErrors::TrialsDisabled);
+        let uri = plan.tier(tier_index)?.uri.clone();
+
+        require!(name.len() <= 32, SolVeilErrors::NameTooLong);
+        require!(symbol.len() <= 10, SolVeilErrors::SymbolTooLong);
+
+        let plan_key = plan.key();
+        let seed_key = plan.subscription_seed(ctx.accounts.user.key(), ctx.accounts.nft_mint.key());
+        let index = ctx.accounts.subscriber_index.next_index;
+        let index_bytes = index.to_le_bytes();
+        let bump = [ctx.bumps.user_subscription];
+        let sub_seeds_inner: &[&[u8]] = &[
+            b"user_subscription",
+            plan_key.as_ref(),
+            seed_key.as_ref(),
+            &index_bytes,
+            &bump,
+        ];
+        let sub_seeds: &[&[&[u8]]] = &[sub_seeds_inner];
+
+        SubscriptionNft {
+            payer: ctx.accounts.user.to_account_info(),
+            owner: ctx.accounts.user.to_account_info(),
+            user_subscription: ctx.accounts.user_subscription.to_account_info(),
+            nft_mint: ctx.accounts.nft_mint.to_account_info(),
+            nft_ata: ctx.accounts.nft_ata.to_account_info(),
+            metadata: ctx.accounts.metadata.to_account_info(),
+            master_edition: ctx.accounts.master_edition.to_account_info(),
+            collection_mint: ctx.accounts.collection_mint.to_account_info(),
+            collection_metadata: ctx.accounts.collection_metadata.to_account_info(),
+            collection_master_edition: ctx.accounts.collection_master_edition.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            token_metadata_program: ctx.accounts.token_metadata_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+        }
+        .mint(plan, sub_seeds, name, symbol, uri)?;
+
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        let trial_record = &mut ctx.accounts.trial_record;
+        trial_record.plan = plan_key;
+        trial_record.user = ctx.accounts.user.key();
+        trial_record.started_at = current_time;
+        trial_record.bump = ctx.bumps.trial_record;
+
         let user_sub = &mut ctx.accounts.user_subscription;
-        user_sub.plan = ctx.accounts.plan.key();
+        user_sub.plan = plan_key;
         user_sub.subscription_mint = ctx.accounts.nft_mint.key();
-        user_sub.start_time = Clock::get()?.unix_timestamp as u64;
-        user_sub.total_deposit_amount = amount;
-        user_sub.claimed_by_creator_amount = upfront;
-        user_sub.refund_token_amount = 0;
+        user_sub.tier = tier_index;
+        user_sub.start_time = current_time;
+        user_sub.expires_at = current_time
+            .checked_add(plan.trial_duration)
+            .ok_or(SolVeilErrors::MathOverflow)?;
         user_sub.is_active = true;
+        user_sub.trial = true;
         user_sub.bump = ctx.bumps.user_subscription;
+        user_sub.index = index;
+        SubscriberIndex::record(
+            &mut ctx.accounts.subscriber_index,
+            plan_key,
+            ctx.accounts.user.key(),
+            ctx.bumps.subscriber_index,
+            user_sub.key(),
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        emit!(TrialStarted {
+            plan: plan_key,
+            user_subscription: user_sub.key(),
+            subscriber: ctx.accounts.user.key(),
+            nft_mint: user_sub.subscription_mint,
+            tier: tier_index,
+            expires_at: user_sub.expires_at,
+        });
+
+        let plan = &mut ctx.accounts.plan;
+        plan.active_subscribers = plan
+            .active_subscribers
+            .checked_add(1)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Pays for the trial's tier, keeping the NFT. The paid period starts right away, since its
+    /// upfront share goes to the creator immediately.
+    pub fn convert_trial(ctx: Context<ConvertTrial>) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        require!(
+            !ctx.accounts.user_subscription.is_expired(plan, current_time),
+            SolVeilErrors::SubscriptionExpired
+        );
+        let tier = plan.tier(ctx.accounts.user_subscription.tier)?;
+        let period = tier.vesting_duration;
+
+        let mut vault = PlanVault::new(
+            plan,
+            ctx.accounts.vault.as_deref_mut(),
+            ctx.accounts.payment_mint.as_deref(),
+            ctx.accounts.payment_token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+            &ctx.accounts.system_program,
+        )?;
+        let user_payment = vault.payment_account(
+            ctx.accounts.user_token.as_ref().map(|a| a.to_account_info()),
+            Some(ctx.accounts.user.to_account_info()),
+        )?;
+        let creator_payment = vault.payment_account(
+            ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+            ctx.accounts.creator.as_ref().map(|a| a.to_account_info()),
+        )?;
+        let payment = vault.collect_purchase(
+            &ctx.accounts.protocol_fee,
+            tier,
+            user_payment,
+            ctx.accounts.user.to_account_info(),
+            creator_payment,
+        )?;
+        let upfront = payment.upfront;
+        let deposit = payment.deposit()?;
+
+        // The trial was free, so the plan's cliff starts with this first payment
+        let user_sub = &mut ctx.accounts.user_subscription;
+        user_sub.start_time = current_time;
+        user_sub.cliff_end = current_time
+            .checked_add(plan.cliff_duration)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        user_sub.add_tranche(
+            VestingTranche {
+                start_time: current_time,
+                duration: period,
+                amount: deposit,
+                upfront_amount: upfront,
+            },
+            current_time,
+        )?;
+        user_sub.claimed_by_creator_amount = user_sub
+            .claimed_by_creator_amount
+            .checked_add(upfront)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        user_sub.trial = false;
+
+        emit!(SubscriptionPurchased {
+            plan: user_sub.plan,
+            user_subscription: user_sub.key(),
+            subscriber: ctx.accounts.user.key(),
+            nft_mint: user_sub.subscription_mint,
+            tier: user_sub.tier,
+            deposit,
+            upfront,
+            protocol_fee: payment.protocol_fee,
+            expires_at: user_sub.expires_at,
+            seats: 0,
+        });
+
+        ctx.accounts.plan.record_flows(deposit, upfront, 0)?;
 
         Ok(())
     }
 
-    pub fn close_subscription(ctx: Context<CloseSubscription>) -> Result<()> {
+    /// Settles, refunds and burns the NFT. With `archive` the deactivated subscription is kept
+    /// as a record of the refund; otherwise its rent goes back to the subscriber.
+    pub fn close_subscription(ctx: Context<CloseSubscription>, archive: bool) -> Result<()> {
         let plan = &ctx.accounts.plan;
         let user_sub = &ctx.accounts.user_subscription;
         let current_time = Clock::get()?.unix_timestamp as u64;
         
         require!(user_sub.is_active, SolVeilErrors::SubscriptionNotActive);
         
-        let elapsed = current_time.saturating_sub(user_sub.start_time);
-        let upfront = ((plan.upfront_percentage as u128 * user_sub.total_deposit_amount as u128)
-            / 100) as u64;
-        let remaining = user_sub.total_deposit_amount.saturating_sub(upfront);
-        let vested_linear = if plan.vesting_duration == 0 || elapsed >= plan.vesting_duration {
-            remaining
-        } else {
-            ((remaining as u128 * elapsed as u128) / plan.vesting_duration as u128) as u64
-        };
-        let vested = upfront + vested_linear;
+        let vested = user_sub.vested_amount(plan, current_time);
         let refundable = user_sub.total_deposit_amount.saturating_sub(vested);
         let unclaimed = vested.saturating_sub(user_sub.claimed_by_creator_amount);
 
-        let plan_seeds_inner: &[&[u8]] = &[
-            b"plan",
-            plan.creator.as_ref(),
-            &plan.seed,
-            &[plan.bump],
-        ];
-        let plan_seeds: &[&[&[u8]]] = &[plan_seeds_inner];
+        let vault = PlanVault::new(
+            plan,
+            ctx.accounts.vault.as_deref_mut(),
+            ctx.accounts.payment_mint.as_deref(),
+            ctx.accounts.payment_token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+            &ctx.accounts.system_program,
+        )?;
 
         // Claim unclaimed vested to creator
         if unclaimed > 0 {
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.vault.to_account_info(),
-                        to: ctx.accounts.creator_token.to_account_info(),
-                        authority: ctx.accounts.plan.to_account_info(),
-                    },
-                    plan_seeds,
-                ),
-                unclaimed,
+            let creator_payment = vault.payment_account(
+                ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+                ctx.accounts.creator.as_ref().map(|a| a.to_account_info()),
             )?;
+            vault.pay_creator(plan, &ctx.accounts.protocol_fee, creator_payment, unclaimed)?;
         }
 
         // Refund unvested to user
         if refundable > 0 {
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.vault.to_account_info(),
-                        to: ctx.accounts.user_token.to_account_info(),
-                        authority: ctx.accounts.plan.to_account_info(),
-                    },
-                    plan_seeds,
-                ),
-                refundable,
-            )?;
+            let user_payment = match user_sub.refund_destination {
+                Some(_) => vault.payment_account(
+                    ctx.accounts.refund_token.as_ref().map(|a| a.to_account_info()),
+                    ctx.accounts.refund_wallet.as_ref().map(|a| a.to_account_info()),
+                )?,
+                None => vault.payment_account(
+                    ctx.accounts.user_token.as_ref().map(|a| a.to_account_info()),
+                    Some(ctx.accounts.user.to_account_info()),
+                )?,
+            };
+            vault.withdraw(plan, user_payment, refundable)?;
+        }
+
+        // Thaw the NFT so it can be burned (transferable NFTs are never frozen)
+        if !plan.transferable {
+            let (metadata_pda, _) = MetadataAccount::find_pda(&ctx.accounts.nft_mint.key());
+            require_keys_eq!(metadata_pda, ctx.accounts.metadata.key());
+            let (edition_pda, _) = MasterEdition::find_pda(&ctx.accounts.nft_mint.key());
+            require_keys_eq!(edition_pda, ctx.accounts.master_edition.key());
+
+            let plan_key = plan.key();
+            let user_key = ctx.accounts.user.key();
+            let index_bytes = user_sub.index.to_le_bytes();
+            let bump = [user_sub.bump];
+            let sub_seeds_inner: &[&[u8]] = &[
+                b"user_subscription",
+                plan_key.as_ref(),
+                user_key.as_ref(),
+                &index_bytes,
+                &bump,
+            ];
+            let sub_seeds: &[&[&[u8]]] = &[sub_seeds_inner];
+
+            thaw_delegated_account(CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                ThawDelegatedAccount {
+                    metadata: ctx.accounts.metadata.to_account_info(),
+                    delegate: ctx.accounts.user_subscription.to_account_info(),
+                    token_account: ctx.accounts.nft_ata.to_account_info(),
+                    edition: ctx.accounts.master_edition.to_account_info(),
+                    mint: ctx.accounts.nft_mint.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+                sub_seeds,
+            ))?;
         }
 
         // Burn NFT
@@ -283,162 +550,2474 @@ pub mod solvency {
             )
         )?;
 
-        Ok(())
-    }
+        emit!(SubscriptionClosed {
+            plan: ctx.accounts.plan.key(),
+            user_subscription: ctx.accounts.user_subscription.key(),
+            subscriber: ctx.accounts.user.key(),
+            vested,
+            paid_to_creator: unclaimed,
+            refund: refundable,
+        });
 
-    pub fn renew_subscription(ctx: Context<RenewSubscription>, amount: u64) -> Result<()> {
-        require!(amount > 0, SolVeilErrors::InvalidAmount);
-        
-        token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_token.to_account_info(),
-                    to: ctx.accounts.vault.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
-                },
-            ),
-            amount,
-        )?;
+        let plan = &mut ctx.accounts.plan;
+        plan.active_subscribers = plan.active_subscribers.saturating_sub(1);
+        plan.record_flows(0, unclaimed, refundable)?;
 
-        let user_sub = &mut ctx.accounts.user_subscription;
-        user_sub.total_deposit_amount = user_sub.total_deposit_amount.checked_add(amount)
-            .ok_or(SolVeilErrors::MathOverflow)?;
+        if archive {
+            ctx.accounts.user_subscription.archive(vested, refundable, current_time);
+        } else {
+            ctx.accounts.user_subscription.close(ctx.accounts.user.to_account_info())?;
+        }
 
         Ok(())
     }
 
-    pub fn claim_tokens(ctx: Context<ClaimTokens>) -> Result<()> {
+    /// Closes a subscription on a transferable plan whose NFT is no longer in an ATA. The
+    /// signer either proves they still hold it in another token account (which is burned), or,
+    /// once it was burned outright, that they are the original buyer: the subscription is
+    /// listed in their `SubscriberIndex`. Non-transferable NFTs are frozen in the subscriber's
+    /// ATA since mint, so those subscriptions always close through `close_subscription`.
+    pub fn recover_subscription(ctx: Context<RecoverSubscription>) -> Result<()> {
         let plan = &ctx.accounts.plan;
-        let user_sub = &mut ctx.accounts.user_subscription;
+        let user_sub = &ctx.accounts.user_subscription;
         let current_time = Clock::get()?.unix_timestamp as u64;
-        
-        require!(user_sub.is_active, SolVeilErrors::SubscriptionNotActive);
-        
-        let elapsed = current_time.saturating_sub(user_sub.start_time);
-        let upfront = ((plan.upfront_percentage as u128 * user_sub.total_deposit_amount as u128)
-            / 100) as u64;
-        let remaining = user_sub.total_deposit_amount.saturating_sub(upfront);
-        let vested_linear = if plan.vesting_duration == 0 || elapsed >= plan.vesting_duration {
-            remaining
-        } else {
-            ((remaining as u128 * elapsed as u128) / plan.vesting_duration as u128) as u64
-        };
-        let vested = upfront + vested_linear;
-        let claimable = vested.saturating_sub(user_sub.claimed_by_creator_amount);
 
-        let plan_seeds_inner: &[&[u8]] = &[
-            b"plan",
-            plan.creator.as_ref(),
-            &plan.seed,
-            &[plan.bump],
-        ];
-        let plan_seeds: &[&[&[u8]]] = &[plan_seeds_inner];
+        require!(user_sub.is_active, SolVeilErrors::SubscriptionNotActive);
 
-        if claimable > 0 {
-            token::transfer(
-                CpiContext::new_with_signer(
+        match &ctx.accounts.nft_holding {
+            Some(nft_holding) => token::burn(
+                CpiContext::new(
                     ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.vault.to_account_info(),
-                        to: ctx.accounts.creator_token.to_account_info(),
-                        authority: ctx.accounts.plan.to_account_info(),
+                    Burn {
+                        mint: ctx.accounts.nft_mint.to_account_info(),
+                        from: nft_holding.to_account_info(),
+                        authority: ctx.accounts.user.to_account_info(),
                     },
-                    plan_seeds,
                 ),
-                claimable,
+                1,
+            )?,
+            None => {
+                require!(ctx.accounts.nft_mint.supply == 0, SolVeilErrors::NftNotLost);
+                let original_buyer = ctx
+                    .accounts
+                    .subscriber_index
+                    .as_ref()
+                    .is_some_and(|index| index.subscriptions.contains(&user_sub.key()));
+                require!(original_buyer, SolVeilErrors::NotOriginalSubscriber);
+            }
+        }
+
+        let vested = user_sub.vested_amount(plan, current_time);
+        let refundable = user_sub.total_deposit_amount.saturating_sub(vested);
+        let unclaimed = vested.saturating_sub(user_sub.claimed_by_creator_amount);
+
+        let vault = PlanVault::new(
+            plan,
+            ctx.accounts.vault.as_deref_mut(),
+            ctx.accounts.payment_mint.as_deref(),
+            ctx.accounts.payment_token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+            &ctx.accounts.system_program,
+        )?;
+        if unclaimed > 0 {
+            let creator_payment = vault.payment_account(
+                ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+                ctx.accounts.creator.as_ref().map(|a| a.to_account_info()),
             )?;
-            user_sub.claimed_by_creator_amount = user_sub.claimed_by_creator_amount
-                .checked_add(claimable)
-                .ok_or(SolVeilErrors::MathOverflow)?;
+            vault.pay_creator(plan, &ctx.accounts.protocol_fee, creator_payment, unclaimed)?;
+        }
+        if refundable > 0 {
+            let user_payment = match user_sub.refund_destination {
+                Some(_) => vault.payment_account(
+                    ctx.accounts.refund_token.as_ref().map(|a| a.to_account_info()),
+                    ctx.accounts.refund_wallet.as_ref().map(|a| a.to_account_info()),
+                )?,
+                None => vault.payment_account(
+                    ctx.accounts.user_token.as_ref().map(|a| a.to_account_info()),
+                    Some(ctx.accounts.user.to_account_info()),
+                )?,
+            };
+            vault.withdraw(plan, user_payment, refundable)?;
         }
 
+        emit!(SubscriptionClosed {
+            plan: plan.key(),
+            user_subscription: user_sub.key(),
+            subscriber: ctx.accounts.user.key(),
+            vested,
+            paid_to_creator: unclaimed,
+            refund: refundable,
+        });
+
+        let plan = &mut ctx.accounts.plan;
+        plan.active_subscribers = plan.active_subscribers.saturating_sub(1);
+        plan.record_flows(0, unclaimed, refundable)?;
+
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-#[instruction(plan_seed: String)]
-pub struct CreatePlan<'info> {
-    #[account(
-        init,
-        payer = creator,
-        space = 8 + SubscriptionPlan::LEN,
-        seeds = [b"plan", creator.key().as_ref(), plan_seed.as_bytes()],
-        bump
-    )]
-    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    /// Lets the creator terminate a subscription: vested funds are settled to the creator, the
+    /// unvested remainder is refunded to the subscriber and the subscription is deactivated.
+    /// Non-transferable NFTs are already frozen. Transferable NFTs are never delegated to the
+    /// subscription PDA and can't be frozen, so their name gets a `CANCELLED_NAME_PREFIX`.
+    pub fn creator_cancel_subscription(ctx: Context<CreatorCancelSubscription>) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let user_sub = &ctx.accounts.user_subscription;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+
+        let vested = user_sub.vested_amount(plan, current_time);
+        let refundable = user_sub.total_deposit_amount.saturating_sub(vested);
+        let unclaimed = vested.saturating_sub(user_sub.claimed_by_creator_amount);
+
+        let vault = PlanVault::new(
+            plan,
+            ctx.accounts.vault.as_deref_mut(),
+            ctx.accounts.payment_mint.as_deref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+            &ctx.accounts.system_program,
+        )?;
+        if unclaimed > 0 {
+            let creator_payment = vault.payment_account(
+                ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+                Some(ctx.accounts.creator.to_account_info()),
+            )?;
+            vault.pay_creator(plan, &ctx.accounts.protocol_fee, creator_payment, unclaimed)?;
+        }
+        if refundable > 0 {
+            let subscriber_payment = match user_sub.refund_destination {
+                Some(_) => vault.payment_account(
+                    ctx.accounts.refund_token.as_ref().map(|a| a.to_account_info()),
+                    ctx.accounts.refund_wallet.as_ref().map(|a| a.to_account_info()),
+                )?,
+                None => vault.payment_account(
+                    ctx.accounts.subscriber_token.as_ref().map(|a| a.to_account_info()),
+                    Some(ctx.accounts.subscriber.to_account_info()),
+                )?,
+            };
+            vault.withdraw(plan, subscriber_payment, refundable)?;
+        }
+
+        // Non-transferable NFTs have been frozen since mint; rename transferable ones so
+        // marketplaces show them as cancelled
+        if plan.transferable {
+            let (metadata_pda, _) = MetadataAccount::find_pda(&ctx.accounts.nft_mint.key());
+            require_keys_eq!(metadata_pda, ctx.accounts.metadata.key());
+
+            let plan_key = plan.key();
+            let seed_key = plan.subscription_seed(
+                ctx.accounts.subscriber.key(),
+                ctx.accounts.nft_mint.key(),
+            );
+            let index_bytes = user_sub.index.to_le_bytes();
+            let bump = [user_sub.bump];
+            let sub_seeds_inner: &[&[u8]] = &[
+                b"user_subscription",
+                plan_key.as_ref(),
+                seed_key.as_ref(),
+                &index_bytes,
+                &bump,
+            ];
+
+            let metadata = &ctx.accounts.metadata;
+            let name: String = format!(
+                "{}{}",
+                CANCELLED_NAME_PREFIX,
+                metadata.name.trim_end_matches('\0')
+            )
+            .chars()
+            .take(mpl_token_metadata::MAX_NAME_LENGTH)
+            .collect();
+            let data_v2 = DataV2 {
+                name,
+                symbol: metadata.symbol.trim_end_matches('\0').to_string(),
+                uri: metadata.uri.trim_end_matches('\0').to_string(),
+                seller_fee_basis_points: metadata.seller_fee_basis_points,
+                creators: metadata.creators.clone(),
+                collection: metadata.collection.clone(),
+                uses: metadata.uses.clone(),
+            };
+
+            update_metadata_accounts_v2(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_metadata_program.to_account_info(),
+                    UpdateMetadataAccountsV2 {
+                        metadata: ctx.accounts.metadata.to_account_info(),
+                        update_authority: user_sub.to_account_info(),
+                    },
+                    &[sub_seeds_inner],
+                ),
+                None,
+                Some(data_v2),
+                None,
+                None,
+            )?;
+        }
+
+        emit!(SubscriptionCancelled {
+            plan: plan.key(),
+            user_subscription: user_sub.key(),
+            subscriber: ctx.accounts.subscriber.key(),
+            vested,
+            paid_to_creator: unclaimed,
+            refund: refundable,
+        });
+
+        ctx.accounts.user_subscription.archive(vested, refundable, current_time);
+
+        let plan = &mut ctx.accounts.plan;
+        plan.active_subscribers = plan.active_subscribers.saturating_sub(1);
+        plan.record_flows(0, unclaimed, refundable)?;
+
+        Ok(())
+    }
+
+    pub fn renew_subscription(ctx: Context<RenewSubscription>, amount: u64) -> Result<()> {
+        require!(amount > 0, SolVeilErrors::InvalidAmount);
+
+        let plan = &ctx.accounts.plan;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        require!(
+            !ctx.accounts.user_subscription.is_expired(plan, current_time),
+            SolVeilErrors::SubscriptionExpired
+        );
+
+        let mut vault = PlanVault::new(
+            plan,
+            ctx.accounts.vault.as_mut(),
+            ctx.accounts.payment_mint.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+            &ctx.accounts.system_program,
+        )?;
+        let user_payment = vault.payment_account(
+            ctx.accounts.user_token.as_ref().map(|a| a.to_account_info()),
+            Some(ctx.accounts.user.to_account_info()),
+        )?;
+        let received =
+            vault.deposit(user_payment, ctx.accounts.user.to_account_info(), &[], amount)?;
+
+        let user_sub = &mut ctx.accounts.user_subscription;
+        let tier = &plan.tier(user_sub.tier)?.for_seats(user_sub.seats)?;
+        user_sub.renew(tier, amount, received, current_time)?;
+        emit!(Renewed {
+            plan: user_sub.plan,
+            user_subscription: user_sub.key(),
+            amount: received,
+            expires_at: user_sub.expires_at,
+            auto_renewal: false,
+        });
+        ctx.accounts.plan.record_flows(received, 0, 0)?;
+
+        Ok(())
+    }
+
+    pub fn enable_auto_renewal(ctx: Context<EnableAutoRenewal>, spending_cap: u64) -> Result<()> {
+        require!(spending_cap > 0, SolVeilErrors::InvalidAmount);
+
+        // Let the plan PDA pull renewals from the subscriber's payment account, up to the cap
+        token_interface::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::Approve {
+                    to: ctx.accounts.user_token.to_account_info(),
+                    delegate: ctx.accounts.plan.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            spending_cap,
+        )?;
+
+        let user_sub = &mut ctx.accounts.user_subscription;
+        user_sub.auto_renew_enabled = true;
+        user_sub.auto_renew_token = ctx.accounts.user_token.key();
+        user_sub.auto_renew_cap = spending_cap;
+        user_sub.auto_renew_spent = 0;
+
+        Ok(())
+    }
+
+    pub fn disable_auto_renewal(ctx: Context<DisableAutoRenewal>) -> Result<()> {
+        token_interface::revoke(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::Revoke {
+                source: ctx.accounts.user_token.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            },
+        ))?;
+
+        ctx.accounts.user_subscription.auto_renew_enabled = false;
+
+        Ok(())
+    }
+
+    pub fn process_auto_renewal(ctx: Context<ProcessAutoRenewal>) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let user_sub = &ctx.accounts.user_subscription;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+
+        require!(user_sub.auto_renew_enabled, SolVeilErrors::AutoRenewalDisabled);
+        require!(
+            !user_sub.is_expired(plan, current_time),
+            SolVeilErrors::SubscriptionExpired
+        );
+        require!(
+            current_time.saturating_add(AUTO_RENEWAL_WINDOW) >= user_sub.expires_at,
+            SolVeilErrors::RenewalNotDue
+        );
+
+        let tier = &plan.tier(user_sub.tier)?.for_seats(user_sub.seats)?;
+        let amount = tier.price;
+        let spent = user_sub
+            .auto_renew_spent
+            .checked_add(amount)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        require!(spent <= user_sub.auto_renew_cap, SolVeilErrors::AutoRenewalCapExceeded);
+
+        let plan_seeds_inner: &[&[u8]] = &[
+            b"plan",
+            plan.creator.as_ref(),
+            &plan.seed,
+            &[plan.bump],
+        ];
+        let plan_seeds: &[&[&[u8]]] = &[plan_seeds_inner];
+
+        // The plan PDA pulls the renewal as the delegate approved in `enable_auto_renewal`
+        let mut vault = PlanVault::Token {
+            vault: &mut ctx.accounts.vault,
+            mint: &ctx.accounts.payment_mint,
+            token_program: ctx.accounts.token_program.to_account_info(),
+        };
+        let received = vault.deposit(
+            ctx.accounts.user_token.to_account_info(),
+            plan.to_account_info(),
+            plan_seeds,
+            amount,
+        )?;
+
+        let user_sub = &mut ctx.accounts.user_subscription;
+        user_sub.renew(tier, amount, received, current_time)?;
+        user_sub.auto_renew_spent = spent;
+        emit!(Renewed {
+            plan: user_sub.plan,
+            user_subscription: user_sub.key(),
+            amount: received,
+            expires_at: user_sub.expires_at,
+            auto_renewal: true,
+        });
+        ctx.accounts.plan.record_flows(received, 0, 0)?;
+
+        Ok(())
+    }
+
+    pub fn claim_tokens(ctx: Context<ClaimTokens>) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let user_sub = &mut ctx.accounts.user_subscription;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        
+        require!(user_sub.is_active, SolVeilErrors::SubscriptionNotActive);
+        
+        let vested = user_sub.vested_amount(plan, current_time);
+        let claimable = vested.saturating_sub(user_sub.claimed_by_creator_amount);
+
+        if claimable > 0 {
+            let vault = PlanVault::new(
+                plan,
+                ctx.accounts.vault.as_mut(),
+                ctx.accounts.payment_mint.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+                ctx.accounts.sol_vault.as_ref(),
+                &ctx.accounts.system_program,
+            )?;
+            let creator_payment = vault.payment_account(
+                ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+                Some(ctx.accounts.creator.to_account_info()),
+            )?;
+            vault.pay_creator(plan, &ctx.accounts.protocol_fee, creator_payment, claimable)?;
+            user_sub.claimed_by_creator_amount = user_sub.claimed_by_creator_amount
+                .checked_add(claimable)
+                .ok_or(SolVeilErrors::MathOverflow)?;
+            emit!(CreatorClaimed {
+                plan: user_sub.plan,
+                user_subscription: user_sub.key(),
+                vested,
+                amount: claimable,
+                claimed_total: user_sub.claimed_by_creator_amount,
+            });
+            ctx.accounts.plan.record_flows(0, claimable, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Claims every active `UserSubscription` passed as writable remaining accounts in one
+    /// vault transfer.
+    pub fn claim_many<'info>(ctx: Context<'_, '_, 'info, 'info, ClaimMany<'info>>) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+
+        let mut total_claimable: u64 = 0;
+        for info in ctx.remaining_accounts.iter() {
+            let mut user_sub = Account::<UserSubscription>::try_from(info)?;
+            require_keys_eq!(user_sub.plan, plan.key(), SolVeilErrors::InvalidPlan);
+            if !user_sub.is_active {
+                continue;
+            }
+
+            let vested = user_sub.vested_amount(plan, current_time);
+            let claimable = vested.saturating_sub(user_sub.claimed_by_creator_amount);
+            if claimable == 0 {
+                continue;
+            }
+
+            // Persist right away so an account listed twice has nothing left to claim
+            user_sub.claimed_by_creator_amount = user_sub
+                .claimed_by_creator_amount
+                .checked_add(claimable)
+                .ok_or(SolVeilErrors::MathOverflow)?;
+            user_sub.exit(&crate::ID)?;
+            emit!(CreatorClaimed {
+                plan: user_sub.plan,
+                user_subscription: user_sub.key(),
+                vested,
+                amount: claimable,
+                claimed_total: user_sub.claimed_by_creator_amount,
+            });
+            total_claimable = total_claimable
+                .checked_add(claimable)
+                .ok_or(SolVeilErrors::MathOverflow)?;
+        }
+
+        if total_claimable > 0 {
+            let vault = PlanVault::new(
+                plan,
+                ctx.accounts.vault.as_mut(),
+                ctx.accounts.payment_mint.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+                ctx.accounts.sol_vault.as_ref(),
+                &ctx.accounts.system_program,
+            )?;
+            let creator_payment = vault.payment_account(
+                ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+                Some(ctx.accounts.creator.to_account_info()),
+            )?;
+            vault.pay_creator(plan, &ctx.accounts.protocol_fee, creator_payment, total_claimable)?;
+            ctx.accounts.plan.record_flows(0, total_claimable, 0)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn change_tier(ctx: Context<ChangeTier>, new_tier_index: u8) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let user_sub = &ctx.accounts.user_subscription;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+
+        require!(user_sub.is_active, SolVeilErrors::SubscriptionNotActive);
+        require!(
+            !user_sub.is_expired(plan, current_time),
+            SolVeilErrors::SubscriptionExpired
+        );
+        require!(new_tier_index != user_sub.tier, SolVeilErrors::SameTier);
+
+        let new_tier = &plan.tier(new_tier_index)?.for_seats(user_sub.seats)?;
+        let new_uri = new_tier.uri.clone();
+        let new_period = new_tier.vesting_duration;
+
+        // Settle the current period: vested part goes to the creator, the rest is credit
+        let vested = user_sub.vested_amount(plan, current_time);
+        let unclaimed = vested.saturating_sub(user_sub.claimed_by_creator_amount);
+        let credit = user_sub.total_deposit_amount.saturating_sub(vested);
+
+        let price = new_tier.price;
+        let upfront_percentage = new_tier.upfront_percentage;
+
+        let mut vault = PlanVault::new(
+            plan,
+            ctx.accounts.vault.as_deref_mut(),
+            ctx.accounts.payment_mint.as_deref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+            &ctx.accounts.system_program,
+        )?;
+        let user_payment = vault.payment_account(
+            ctx.accounts.user_token.as_ref().map(|a| a.to_account_info()),
+            Some(ctx.accounts.user.to_account_info()),
+        )?;
+
+        // Collect the difference when the new tier costs more than the credit; the new
+        // deposit is the credit plus whatever the vault actually received
+        let mut deposit = price;
+        let mut received = 0;
+        if price > credit {
+            received = vault.deposit(
+                user_payment.clone(),
+                ctx.accounts.user.to_account_info(),
+                &[],
+                price - credit,
+            )?;
+            deposit = credit
+                .checked_add(received)
+                .ok_or(SolVeilErrors::MathOverflow)?;
+        }
+
+        let upfront = ((upfront_percentage as u128 * deposit as u128) / 100) as u64;
+        let to_creator = unclaimed
+            .checked_add(upfront)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+
+        if to_creator > 0 {
+            let creator_payment = vault.payment_account(
+                ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+                ctx.accounts.creator.as_ref().map(|a| a.to_account_info()),
+            )?;
+            vault.pay_creator(plan, &ctx.accounts.protocol_fee, creator_payment, to_creator)?;
+        }
+
+        // Refund the credit left over after paying for the new tier
+        if credit > price {
+            let refund_payment = match user_sub.refund_destination {
+                Some(_) => vault.payment_account(
+                    ctx.accounts.refund_token.as_ref().map(|a| a.to_account_info()),
+                    ctx.accounts.refund_wallet.as_ref().map(|a| a.to_account_info()),
+                )?,
+                None => user_payment,
+            };
+            vault.withdraw(plan, refund_payment, credit - price)?;
+        }
+
+        // Point the existing NFT at the new tier's metadata
+        let (metadata_pda, _) = MetadataAccount::find_pda(&user_sub.subscription_mint);
+        require_keys_eq!(metadata_pda, ctx.accounts.metadata.key());
+
+        let plan_key = plan.key();
+        let seed_key = plan.subscription_seed(ctx.accounts.user.key(), user_sub.subscription_mint);
+        let index_bytes = user_sub.index.to_le_bytes();
+        let bump = [user_sub.bump];
+        let sub_seeds_inner: &[&[u8]] = &[
+            b"user_subscription",
+            plan_key.as_ref(),
+            seed_key.as_ref(),
+            &index_bytes,
+            &bump,
+        ];
+        let sub_seeds: &[&[&[u8]]] = &[sub_seeds_inner];
+
+        let metadata = &ctx.accounts.metadata;
+        let data_v2 = DataV2 {
+            name: metadata.name.trim_end_matches('\0').to_string(),
+            symbol: metadata.symbol.trim_end_matches('\0').to_string(),
+            uri: new_uri,
+            seller_fee_basis_points: metadata.seller_fee_basis_points,
+            creators: metadata.creators.clone(),
+            collection: metadata.collection.clone(),
+            uses: metadata.uses.clone(),
+        };
+
+        update_metadata_accounts_v2(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                UpdateMetadataAccountsV2 {
+                    metadata: ctx.accounts.metadata.to_account_info(),
+                    update_authority: ctx.accounts.user_subscription.to_account_info(),
+                },
+                sub_seeds,
+            ),
+            None,
+            Some(data_v2),
+            None,
+            None,
+        )?;
+
+        // Restart the vesting clock on the new tier. `cliff_end` stays, so changing tiers
+        // doesn't restart the plan's cliff
+        let user_sub = &mut ctx.accounts.user_subscription;
+        let old_tier = user_sub.tier;
+        user_sub.tier = new_tier_index;
+        user_sub.start_time = current_time;
+        user_sub.tranches.clear();
+        user_sub.settled_amount = 0;
+        user_sub.total_deposit_amount = 0;
+        user_sub.expires_at = 0;
+        user_sub.add_tranche(
+            VestingTranche {
+                start_time: current_time,
+                duration: new_period,
+                amount: deposit,
+                upfront_amount: upfront,
+            },
+            current_time,
+        )?;
+        user_sub.claimed_by_creator_amount = upfront;
+        // The subscriber's own payment is now mixed into the deposit
+        if received > 0 {
+            user_sub.refund_destination = None;
+        }
+
+        emit!(TierChanged {
+            plan: ctx.accounts.plan.key(),
+            user_subscription: user_sub.key(),
+            subscriber: ctx.accounts.user.key(),
+            old_tier,
+            new_tier: new_tier_index,
+            vested,
+            credit,
+            received,
+            upfront,
+            refund: credit.saturating_sub(price),
+        });
+
+        ctx.accounts
+            .plan
+            .record_flows(received, to_creator, credit.saturating_sub(price))?;
+
+        Ok(())
+    }
+
+    pub fn assert_active_subscription(
+        ctx: Context<AssertActiveSubscription>,
+        min_unvested_amount: Option<u64>,
+    ) -> Result<SubscriptionStatus> {
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        ctx.accounts.user_subscription.active_status(
+            &ctx.accounts.plan,
+            ctx.accounts.holder.key(),
+            min_unvested_amount,
+            current_time,
+        )
+    }
+
+    /// Like `assert_active_subscription`, for a member holding a seat NFT from a seat pool.
+    pub fn assert_active_seat(
+        ctx: Context<AssertActiveSeat>,
+        min_unvested_amount: Option<u64>,
+    ) -> Result<SubscriptionStatus> {
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        let status = ctx.accounts.user_subscription.active_status(
+            &ctx.accounts.plan,
+            ctx.accounts.holder.key(),
+            min_unvested_amount,
+            current_time,
+        )?;
+        Ok(SubscriptionStatus {
+            subscription_mint: ctx.accounts.seat.nft_mint,
+            ..status
+        })
+    }
+
+    /// Mints a seat NFT from the admin's seat pool to `member`. On non-transferable plans the
+    /// member co-signs so the NFT can be frozen in their wallet.
+    pub fn assign_seat(ctx: Context<AssignSeat>, name: String, symbol: String) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let pool = &ctx.accounts.user_subscription;
+        require!(pool.assigned_seats < pool.seats, SolVeilErrors::NoSeatsAvailable);
+        require!(name.len() <= 32, SolVeilErrors::NameTooLong);
+        require!(symbol.len() <= 10, SolVeilErrors::SymbolTooLong);
+        let uri = plan.tier(pool.tier)?.uri.clone();
+
+        let pool_key = pool.key();
+        let member_key = ctx.accounts.member.key();
+        let bump = [ctx.bumps.seat];
+        let seat_seeds_inner: &[&[u8]] = &[b"seat", pool_key.as_ref(), member_key.as_ref(), &bump];
+        let seat_seeds: &[&[&[u8]]] = &[seat_seeds_inner];
+
+        SubscriptionNft {
+            payer: ctx.accounts.admin.to_account_info(),
+            owner: ctx.accounts.member.to_account_info(),
+            user_subscription: ctx.accounts.seat.to_account_info(),
+            nft_mint: ctx.accounts.nft_mint.to_account_info(),
+            nft_ata: ctx.accounts.nft_ata.to_account_info(),
+            metadata: ctx.accounts.metadata.to_account_info(),
+            master_edition: ctx.accounts.master_edition.to_account_info(),
+            collection_mint: ctx.accounts.collection_mint.to_account_info(),
+            collection_metadata: ctx.accounts.collection_metadata.to_account_info(),
+            collection_master_edition: ctx.accounts.collection_master_edition.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            token_metadata_program: ctx.accounts.token_metadata_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+        }
+        .mint(plan, seat_seeds, name, symbol, uri)?;
+
+        let seat = &mut ctx.accounts.seat;
+        seat.pool = pool_key;
+        seat.member = member_key;
+        seat.nft_mint = ctx.accounts.nft_mint.key();
+        seat.assigned_at = Clock::get()?.unix_timestamp as u64;
+        seat.bump = ctx.bumps.seat;
+
+        let pool = &mut ctx.accounts.user_subscription;
+        pool.assigned_seats = pool
+            .assigned_seats
+            .checked_add(1)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+
+        emit!(SeatAssigned {
+            plan: plan.key(),
+            user_subscription: pool_key,
+            seat: seat.key(),
+            member: member_key,
+            nft_mint: seat.nft_mint,
+            assigned_seats: pool.assigned_seats,
+        });
+
+        Ok(())
+    }
+
+    /// Frees a seat and closes its record. The seat NFT is thawed and burned through the seat's
+    /// delegation while it is still in the member's wallet; otherwise it is simply orphaned,
+    /// and gating rejects it once the record is gone.
+    pub fn revoke_seat(ctx: Context<RevokeSeat>) -> Result<()> {
+        let seat = &ctx.accounts.seat;
+        let pool_key = ctx.accounts.user_subscription.key();
+        let bump = [seat.bump];
+        let seat_seeds_inner: &[&[u8]] = &[b"seat", pool_key.as_ref(), seat.member.as_ref(), &bump];
+        let seat_seeds: &[&[&[u8]]] = &[seat_seeds_inner];
+
+        let nft_burned = match &ctx.accounts.seat_nft_ata {
+            Some(nft_ata)
+                if Option::from(nft_ata.delegate) == Some(seat.key())
+                    && nft_ata.delegated_amount >= 1 =>
+            {
+                if nft_ata.is_frozen() {
+                    let (metadata_pda, _) = MetadataAccount::find_pda(&ctx.accounts.nft_mint.key());
+                    require_keys_eq!(metadata_pda, ctx.accounts.metadata.key());
+                    let (edition_pda, _) = MasterEdition::find_pda(&ctx.accounts.nft_mint.key());
+                    require_keys_eq!(edition_pda, ctx.accounts.master_edition.key());
+
+                    thaw_delegated_account(CpiContext::new_with_signer(
+                        ctx.accounts.token_metadata_program.to_account_info(),
+                        ThawDelegatedAccount {
+                            metadata: ctx.accounts.metadata.to_account_info(),
+                            delegate: seat.to_account_info(),
+                            token_account: nft_ata.to_account_info(),
+                            edition: ctx.accounts.master_edition.to_account_info(),
+                            mint: ctx.accounts.nft_mint.to_account_info(),
+                            token_program: ctx.accounts.token_program.to_account_info(),
+                        },
+                        seat_seeds,
+                    ))?;
+                }
+                token::burn(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Burn {
+                            mint: ctx.accounts.nft_mint.to_account_info(),
+                            from: nft_ata.to_account_info(),
+                            authority: seat.to_account_info(),
+                        },
+                        seat_seeds,
+                    ),
+                    1,
+                )?;
+                true
+            }
+            _ => false,
+        };
+
+        let pool = &mut ctx.accounts.user_subscription;
+        pool.assigned_seats = pool.assigned_seats.saturating_sub(1);
+
+        emit!(SeatRevoked {
+            plan: ctx.accounts.plan.key(),
+            user_subscription: pool_key,
+            seat: ctx.accounts.seat.key(),
+            member: ctx.accounts.seat.member,
+            nft_burned,
+            assigned_seats: pool.assigned_seats,
+        });
+
+        Ok(())
+    }
+
+    pub fn expire_subscription(ctx: Context<ExpireSubscription>) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+        let user_sub = &ctx.accounts.user_subscription;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+
+        require!(user_sub.is_active, SolVeilErrors::SubscriptionNotActive);
+        require!(
+            user_sub.is_expired(plan, current_time),
+            SolVeilErrors::SubscriptionNotExpired
+        );
+
+        // Hand whatever the creator has not claimed yet over before deactivating. After a sunset
+        // part of the deposit may never vest; only the subscriber's close can refund that
+        let vested = user_sub.vested_amount(plan, current_time);
+        require!(
+            vested >= user_sub.total_deposit_amount,
+            SolVeilErrors::UnvestedFundsRemain
+        );
+        let unclaimed = vested.saturating_sub(user_sub.claimed_by_creator_amount);
+
+        if unclaimed > 0 {
+            let vault = PlanVault::new(
+                plan,
+                ctx.accounts.vault.as_deref_mut(),
+                ctx.accounts.payment_mint.as_deref(),
+                ctx.accounts.token_program.as_ref(),
+                ctx.accounts.sol_vault.as_ref(),
+                &ctx.accounts.system_program,
+            )?;
+            let creator_payment = vault.payment_account(
+                ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+                ctx.accounts.creator.as_ref().map(|a| a.to_account_info()),
+            )?;
+            vault.pay_creator(plan, &ctx.accounts.protocol_fee, creator_payment, unclaimed)?;
+        }
+
+        // The NFT stays with its holder, but gating rejects inactive subscriptions
+        let user_sub = &mut ctx.accounts.user_subscription;
+        user_sub.claimed_by_creator_amount = user_sub
+            .claimed_by_creator_amount
+            .checked_add(unclaimed)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        user_sub.is_active = false;
+        user_sub.closed_at = current_time;
+
+        emit!(SubscriptionExpired {
+            plan: ctx.accounts.plan.key(),
+            user_subscription: user_sub.key(),
+            vested,
+            paid_to_creator: unclaimed,
+        });
+
+        let plan = &mut ctx.accounts.plan;
+        plan.active_subscribers = plan.active_subscribers.saturating_sub(1);
+        plan.record_flows(0, unclaimed, 0)?;
+
+        Ok(())
+    }
+
+    /// Sets the protocol fee and treasury, creating the config on first use. Only the program's
+    /// upgrade authority may call this.
+    pub fn update_protocol_config(
+        ctx: Context<UpdateProtocolConfig>,
+        fee_bps: u16,
+        treasury: Pubkey,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_PROTOCOL_FEE_BPS, SolVeilErrors::InvalidProtocolFee);
+
+        let config = &mut ctx.accounts.config;
+        config.fee_bps = fee_bps;
+        config.treasury = treasury;
+        config.bump = ctx.bumps.config;
+        Ok(())
+    }
+
+    pub fn pause_plan(ctx: Context<ManagePlan>) -> Result<()> {
+        let plan = &mut ctx.accounts.plan;
+        plan.paused = true;
+        emit!(PlanStatusChanged {
+            plan: plan.key(),
+            paused: plan.paused,
+            sunset_at: plan.sunset_at,
+            closed: false,
+        });
+        Ok(())
+    }
+
+    pub fn resume_plan(ctx: Context<ManagePlan>) -> Result<()> {
+        let plan = &mut ctx.accounts.plan;
+        plan.paused = false;
+        emit!(PlanStatusChanged {
+            plan: plan.key(),
+            paused: plan.paused,
+            sunset_at: plan.sunset_at,
+            closed: false,
+        });
+        Ok(())
+    }
+
+    /// Winds the plan down: no new sales or renewals, and vesting stops at `sunset_at` so
+    /// subscribers can close for the unvested remainder.
+    pub fn sunset_plan(ctx: Context<ManagePlan>, sunset_at: u64) -> Result<()> {
+        let plan = &mut ctx.accounts.plan;
+        let current_time = Clock::get()?.unix_timestamp as u64;
+
+        require!(plan.sunset_at == 0, SolVeilErrors::PlanSunset);
+        require!(sunset_at >= current_time, SolVeilErrors::InvalidSunsetTime);
+
+        plan.sunset_at = sunset_at;
+        emit!(PlanStatusChanged {
+            plan: plan.key(),
+            paused: plan.paused,
+            sunset_at: plan.sunset_at,
+            closed: false,
+        });
+        Ok(())
+    }
+
+    pub fn close_plan(ctx: Context<ClosePlan>) -> Result<()> {
+        let plan = &ctx.accounts.plan;
+
+        // Every deposit must have been paid out to the creator or refunded
+        let paid_out = plan
+            .total_claimed_by_creator
+            .checked_add(plan.total_refunded)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        require!(
+            plan.active_subscribers == 0 && paid_out == plan.total_deposited,
+            SolVeilErrors::PlanNotDrained
+        );
+
+        let vault = PlanVault::new(
+            plan,
+            ctx.accounts.vault.as_deref_mut(),
+            ctx.accounts.payment_mint.as_deref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+            &ctx.accounts.system_program,
+        )?;
+        let creator_payment = vault.payment_account(
+            ctx.accounts.creator_token.as_ref().map(|a| a.to_account_info()),
+            Some(ctx.accounts.creator.to_account_info()),
+        )?;
+        vault.close(plan, creator_payment, ctx.accounts.creator.to_account_info())?;
+        emit!(PlanStatusChanged {
+            plan: plan.key(),
+            paused: plan.paused,
+            sunset_at: plan.sunset_at,
+            closed: true,
+        });
+
+        Ok(())
+    }
+}
+
+/// Shared by `buy_subscription`, `buy_seats` and `gift_subscription`: collects the price from
+/// `payer`, mints the NFT to `owner` and opens the subscription. `seats` is 0 for an individual
+/// subscription.
+fn purchase(
+    accounts: Purchase<'_, '_>,
+    tier_index: u8,
+    seats: u16,
+    name: String,
+    symbol: String,
+    refund_destination: Option<Pubkey>,
+) -> Result<()> {
+    let Purchase {
+        payer,
+        payer_token,
+        owner,
+        plan,
+        payment_mint,
+        vault,
+        sol_vault,
+        creator_token,
+        creator,
+        protocol_fee,
+        subscriber_index,
+        subscriber_index_bump,
+        user_subscription,
+        user_subscription_bump,
+        nft,
+        payment_token_program,
+        system_program,
+    } = accounts;
+    let tier = &plan.tier(tier_index)?.for_seats(seats)?;
+    let period = tier.vesting_duration;
+    let uri = tier.uri.clone();
+    
+    require!(name.len() <= 32, SolVeilErrors::NameTooLong);
+    require!(symbol.len() <= 10, SolVeilErrors::SymbolTooLong);
+
+    let mut vault = PlanVault::new(
+        plan,
+        vault,
+        payment_mint,
+        payment_token_program,
+        sol_vault,
+        system_program,
+    )?;
+    let payer_payment = vault.payment_account(payer_token, Some(payer.clone()))?;
+    let creator_payment = vault.payment_account(creator_token, creator)?;
+    let payment = vault.collect_purchase(
+        protocol_fee,
+        tier,
+        payer_payment,
+        payer.clone(),
+        creator_payment,
+    )?;
+    let upfront = payment.upfront;
+    let deposit = payment.deposit()?;
+
+    // Mint NFT
+    // Bind temporaries first
+    let plan_key = plan.key();  // Pubkey is Copy, so this is fine
+    let seed_key = plan.subscription_seed(owner.key(), nft.nft_mint.key());
+    let index = subscriber_index.next_index;
+    let index_bytes = index.to_le_bytes();
+    let bump = [user_subscription_bump];  // [u8; 1]
+
+    // Now create the owned array
+    let sub_seeds_inner_array = [
+        b"user_subscription" as &[u8],
+        plan_key.as_ref(),
+        seed_key.as_ref(),
+        &index_bytes,
+        &bump,
+    ];
+
+    // Slice it
+    let sub_seeds_inner: &[&[u8]] = &sub_seeds_inner_array;
+
+    // Wrap for CPI (multiple signers, even if one)
+    let sub_seeds: &[&[&[u8]]] = &[sub_seeds_inner];
+
+    nft.mint(plan, sub_seeds, name, symbol, uri)?;
+
+    // Initialize user subscription
+    let user_sub = user_subscription;
+    user_sub.plan = plan_key;
+    user_sub.subscription_mint = nft.nft_mint.key();
+    let current_time = Clock::get()?.unix_timestamp as u64;
+    user_sub.tier = tier_index;
+    user_sub.start_time = current_time;
+    user_sub.cliff_end = current_time
+        .checked_add(plan.cliff_duration)
+        .ok_or(SolVeilErrors::MathOverflow)?;
+    user_sub.add_tranche(
+        VestingTranche {
+            start_time: current_time,
+            duration: period,
+            amount: deposit,
+            upfront_amount: upfront,
+        },
+        current_time,
+    )?;
+    user_sub.claimed_by_creator_amount = upfront;
+    user_sub.refund_token_amount = 0;
+    user_sub.is_active = true;
+    user_sub.bump = user_subscription_bump;
+    user_sub.index = index;
+    user_sub.seats = seats;
+    user_sub.refund_destination = refund_destination;
+    SubscriberIndex::record(
+        subscriber_index,
+        plan_key,
+        owner.key(),
+        subscriber_index_bump,
+        user_sub.key(),
+        payer,
+        system_program.to_account_info(),
+    )?;
+
+    emit!(SubscriptionPurchased {
+        plan: user_sub.plan,
+        user_subscription: user_sub.key(),
+        subscriber: owner.key(),
+        nft_mint: user_sub.subscription_mint,
+        tier: tier_index,
+        deposit,
+        upfront,
+        protocol_fee: payment.protocol_fee,
+        expires_at: user_sub.expires_at,
+        seats,
+    });
+
+    plan.active_subscribers = plan
+        .active_subscribers
+        .checked_add(1)
+        .ok_or(SolVeilErrors::MathOverflow)?;
+    plan.record_flows(deposit, upfront, 0)?;
+
+    Ok(())
+}
+
+/// Accounts `purchase` works on, taken from `BuySubscription` or `GiftSubscription`.
+struct Purchase<'a, 'info> {
+    /// Pays the price and the rent
+    payer: AccountInfo<'info>,
+    payer_token: Option<AccountInfo<'info>>,
+    /// Owns the subscription and its NFT
+    owner: AccountInfo<'info>,
+    plan: &'a mut Account<'info, SubscriptionPlan>,
+    payment_mint: Option<&'a InterfaceAccount<'info, PaymentMint>>,
+    vault: Option<&'a mut InterfaceAccount<'info, PaymentTokenAccount>>,
+    sol_vault: Option<&'a SystemAccount<'info>>,
+    creator_token: Option<AccountInfo<'info>>,
+    creator: Option<AccountInfo<'info>>,
+    protocol_fee: &'a ProtocolFee<'info>,
+    subscriber_index: &'a mut Account<'info, SubscriberIndex>,
+    subscriber_index_bump: u8,
+    user_subscription: &'a mut Account<'info, UserSubscription>,
+    user_subscription_bump: u8,
+    nft: SubscriptionNft<'info>,
+    payment_token_program: Option<&'a Interface<'info, TokenInterface>>,
+    system_program: &'a Program<'info, System>,
+}
+
+impl<'info> BuySubscription<'info> {
+    fn purchase_accounts(&mut self, bumps: &BuySubscriptionBumps) -> Purchase<'_, 'info> {
+        Purchase {
+            nft: SubscriptionNft {
+                payer: self.user.to_account_info(),
+                owner: self.user.to_account_info(),
+                user_subscription: self.user_subscription.to_account_info(),
+                nft_mint: self.nft_mint.to_account_info(),
+                nft_ata: self.nft_ata.to_account_info(),
+                metadata: self.metadata.to_account_info(),
+                master_edition: self.master_edition.to_account_info(),
+                collection_mint: self.collection_mint.to_account_info(),
+                collection_metadata: self.collection_metadata.to_account_info(),
+                collection_master_edition: self.collection_master_edition.to_account_info(),
+                token_program: self.token_program.to_account_info(),
+                token_metadata_program: self.token_metadata_program.to_account_info(),
+                system_program: self.system_program.to_account_info(),
+                rent: self.rent.to_account_info(),
+            },
+            payer: self.user.to_account_info(),
+            payer_token: self.user_token.as_ref().map(|a| a.to_account_info()),
+            owner: self.user.to_account_info(),
+            plan: &mut self.plan,
+            payment_mint: self.payment_mint.as_deref(),
+            vault: self.vault.as_deref_mut(),
+            sol_vault: self.sol_vault.as_ref(),
+            creator_token: self.creator_token.as_ref().map(|a| a.to_account_info()),
+            creator: self.creator.as_ref().map(|a| a.to_account_info()),
+            protocol_fee: &self.protocol_fee,
+            subscriber_index: &mut self.subscriber_index,
+            subscriber_index_bump: bumps.subscriber_index,
+            user_subscription: &mut self.user_subscription,
+            user_subscription_bump: bumps.user_subscription,
+            payment_token_program: self.payment_token_program.as_ref(),
+            system_program: &self.system_program,
+        }
+    }
+}
+
+impl<'info> GiftSubscription<'info> {
+    fn purchase_accounts(&mut self, bumps: &GiftSubscriptionBumps) -> Purchase<'_, 'info> {
+        Purchase {
+            nft: SubscriptionNft {
+                payer: self.payer.to_account_info(),
+                owner: self.recipient.to_account_info(),
+                user_subscription: self.user_subscription.to_account_info(),
+                nft_mint: self.nft_mint.to_account_info(),
+                nft_ata: self.nft_ata.to_account_info(),
+                metadata: self.metadata.to_account_info(),
+                master_edition: self.master_edition.to_account_info(),
+                collection_mint: self.collection_mint.to_account_info(),
+                collection_metadata: self.collection_metadata.to_account_info(),
+                collection_master_edition: self.collection_master_edition.to_account_info(),
+                token_program: self.token_program.to_account_info(),
+                token_metadata_program: self.token_metadata_program.to_account_info(),
+                system_program: self.system_program.to_account_info(),
+                rent: self.rent.to_account_info(),
+            },
+            payer: self.payer.to_account_info(),
+            payer_token: self.payer_token.as_ref().map(|a| a.to_account_info()),
+            owner: self.recipient.to_account_info(),
+            plan: &mut self.plan,
+            payment_mint: self.payment_mint.as_deref(),
+            vault: self.vault.as_deref_mut(),
+            sol_vault: self.sol_vault.as_ref(),
+            creator_token: self.creator_token.as_ref().map(|a| a.to_account_info()),
+            creator: self.creator.as_ref().map(|a| a.to_account_info()),
+            protocol_fee: &self.protocol_fee,
+            subscriber_index: &mut self.subscriber_index,
+            subscriber_index_bump: bumps.subscriber_index,
+            user_subscription: &mut self.user_subscription,
+            user_subscription_bump: bumps.user_subscription,
+            payment_token_program: self.payment_token_program.as_ref(),
+            system_program: &self.system_program,
+        }
+    }
+}
+
+/// Helpers for programs that gate their own instructions on a Capstone subscription.
+#[cfg(feature = "cpi")]
+pub mod gating {
+    use super::*;
+
+    /// Fails unless `holder` owns an active subscription to `plan` with at least
+    /// `min_unvested_amount` still unvested; returns the subscription status on success.
+    pub fn require_active_subscription<'info>(
+        capstone_program: AccountInfo<'info>,
+        plan: AccountInfo<'info>,
+        user_subscription: AccountInfo<'info>,
+        holder: AccountInfo<'info>,
+        nft_ata: AccountInfo<'info>,
+        min_unvested_amount: Option<u64>,
+    ) -> Result<SubscriptionStatus> {
+        let cpi_ctx = CpiContext::new(
+            capstone_program,
+            crate::cpi::accounts::AssertActiveSubscription {
+                plan,
+                user_subscription,
+                holder,
+                nft_ata,
+            },
+        );
+        Ok(crate::cpi::assert_active_subscription(cpi_ctx, min_unvested_amount)?.get())
+    }
+
+    /// Seat-holder counterpart of `require_active_subscription`; `user_subscription` is the
+    /// seat pool and `seat` the holder's seat record.
+    pub fn require_active_seat<'info>(
+        capstone_program: AccountInfo<'info>,
+        plan: AccountInfo<'info>,
+        user_subscription: AccountInfo<'info>,
+        seat: AccountInfo<'info>,
+        holder: AccountInfo<'info>,
+        nft_ata: AccountInfo<'info>,
+        min_unvested_amount: Option<u64>,
+    ) -> Result<SubscriptionStatus> {
+        let cpi_ctx = CpiContext::new(
+            capstone_program,
+            crate::cpi::accounts::AssertActiveSeat {
+                plan,
+                user_subscription,
+                seat,
+                holder,
+                nft_ata,
+            },
+        );
+        Ok(crate::cpi::assert_active_seat(cpi_ctx, min_unvested_amount)?.get())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(plan_seed: String)]
+pub struct CreatePlan<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + SubscriptionPlan::LEN,
+        seeds = [b"plan", creator.key().as_ref(), plan_seed.as_bytes()],
+        bump
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    /// Created with a zero fee by the first plan, so fee-taking instructions can require it
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + ProtocolConfig::LEN,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// Omitted for plans priced in SOL
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump,
+        token::mint = payment_mint,
+        token::authority = plan,
+        token::token_program = payment_token_program
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// SOL plans only: system-owned vault at the same address as the token vault
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"collection", plan.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = plan,
+        mint::freeze_authority = plan
+    )]
+    pub collection_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = creator,
+        associated_token::mint = collection_mint,
+        associated_token::authority = plan
+    )]
+    pub collection_token: Box<Account<'info, TokenAccount>>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub collection_metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    #[account(mut)]
+    pub collection_master_edition: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub payment_token_program: Option<Interface<'info, TokenInterface>>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct BuySubscription<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = !plan.paused @ SolVeilErrors::PlanPaused,
+        constraint = plan.sunset_at == 0 @ SolVeilErrors::PlanSunset
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = user
+    )]
+    pub user_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives the upfront share on SOL plans
+    #[account(mut, address = plan.creator @ SolVeilErrors::Unauthorized)]
+    pub creator: Option<UncheckedAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + SubscriberIndex::LEN,
+        seeds = [b"subscriber_index", plan.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub subscriber_index: Box<Account<'info, SubscriberIndex>>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserSubscription::LEN,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(user.key(), nft_mint.key()).as_ref(),
+            &subscriber_index.next_index.to_le_bytes()
+        ],
+        bump
+    )]
+pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        init,
+        payer = user,
+        mint::decimals = 0,
+        mint::authority = user_subscription,
+        mint::freeze_authority = user_subscription
+    )]
+pub nft_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = nft_mint,
+        associated_token::authority = user
+    )]
+    pub nft_ata: Account<'info, TokenAccount>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    #[account(mut)]
+    pub master_edition: UncheckedAccount<'info>,
+    #[account(
+        address = plan.nft_collection @ SolVeilErrors::InvalidCollection
+    )]
+    pub collection_mint: Box<Account<'info, Mint>>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub collection_metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    pub collection_master_edition: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub payment_token_program: Option<Interface<'info, TokenInterface>>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct GiftSubscription<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Any wallet; must also sign when the plan is not transferable
+    #[account(constraint = plan.transferable || recipient.is_signer @ SolVeilErrors::RecipientMustSign)]
+    pub recipient: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = !plan.paused @ SolVeilErrors::PlanPaused,
+        constraint = plan.sunset_at == 0 @ SolVeilErrors::PlanSunset
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = payer
+    )]
+    pub payer_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives the upfront share on SOL plans
+    #[account(mut, address = plan.creator @ SolVeilErrors::Unauthorized)]
+    pub creator: Option<UncheckedAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + SubscriberIndex::LEN,
+        seeds = [b"subscriber_index", plan.key().as_ref(), recipient.key().as_ref()],
+        bump
+    )]
+    pub subscriber_index: Box<Account<'info, SubscriberIndex>>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + UserSubscription::LEN,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(recipient.key(), nft_mint.key()).as_ref(),
+            &subscriber_index.next_index.to_le_bytes()
+        ],
+        bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        init,
+        payer = payer,
+        mint::decimals = 0,
+        mint::authority = user_subscription,
+        mint::freeze_authority = user_subscription
+    )]
+    pub nft_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = nft_mint,
+        associated_token::authority = recipient
+    )]
+    pub nft_ata: Account<'info, TokenAccount>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    #[account(mut)]
+    pub master_edition: UncheckedAccount<'info>,
+    #[account(
+        address = plan.nft_collection @ SolVeilErrors::InvalidCollection
+    )]
+    pub collection_mint: Box<Account<'info, Mint>>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub collection_metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    pub collection_master_edition: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub payment_token_program: Option<Interface<'info, TokenInterface>>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct StartTrial<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = !plan.paused @ SolVeilErrors::PlanPaused,
+        constraint = plan.sunset_at == 0 @ SolVeilErrors::PlanSunset
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + TrialRecord::LEN,
+        seeds = [b"trial", plan.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub trial_record: Box<Account<'info, TrialRecord>>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + SubscriberIndex::LEN,
+        seeds = [b"subscriber_index", plan.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub subscriber_index: Box<Account<'info, SubscriberIndex>>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserSubscription::LEN,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(user.key(), nft_mint.key()).as_ref(),
+            &subscriber_index.next_index.to_le_bytes()
+        ],
+        bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        init,
+        payer = user,
+        mint::decimals = 0,
+        mint::authority = user_subscription,
+        mint::freeze_authority = user_subscription
+    )]
+    pub nft_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = nft_mint,
+        associated_token::authority = user
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    #[account(mut)]
+    pub master_edition: UncheckedAccount<'info>,
+    #[account(
+        address = plan.nft_collection @ SolVeilErrors::InvalidCollection
+    )]
+    pub collection_mint: Box<Account<'info, Mint>>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub collection_metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    pub collection_master_edition: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ConvertTrial<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = !plan.paused @ SolVeilErrors::PlanPaused,
+        constraint = plan.sunset_at == 0 @ SolVeilErrors::PlanSunset
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
+        constraint = user_subscription.trial @ SolVeilErrors::NotATrial,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(user.key(), user_subscription.subscription_mint).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = user,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = user
+    )]
+    pub user_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives the upfront share on SOL plans
+    #[account(mut, address = plan.creator @ SolVeilErrors::Unauthorized)]
+    pub creator: Option<UncheckedAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
+    pub payment_token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSubscription<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.assigned_seats == 0 @ SolVeilErrors::SeatsStillAssigned,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(user.key(), nft_mint.key()).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = user
+    )]
+    pub user_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        constraint = user_subscription.refund_destination == Some(refund_token.owner) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives refunds on SOL plans when the subscription has a refund destination
+    #[account(
+        mut,
+        constraint = user_subscription.refund_destination == Some(refund_wallet.key()) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_wallet: Option<UncheckedAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives vested SOL on SOL plans
+    #[account(mut, address = plan.creator @ SolVeilErrors::Unauthorized)]
+    pub creator: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        constraint = user_subscription.subscription_mint == nft_mint.key() @ SolVeilErrors::InvalidNftMint
+    )]
+    pub nft_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = user,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Account<'info, TokenAccount>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    pub metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    pub master_edition: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub payment_token_program: Option<Interface<'info, TokenInterface>>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecoverSubscription<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = plan.transferable @ SolVeilErrors::NftFrozen
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.assigned_seats == 0 @ SolVeilErrors::SeatsStillAssigned,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            nft_mint.key().as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump,
+        close = user
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        mut,
+        constraint = user_subscription.subscription_mint == nft_mint.key() @ SolVeilErrors::InvalidNftMint
+    )]
+    pub nft_mint: Box<Account<'info, Mint>>,
+    /// Any token account of the signer's still holding the NFT
+    #[account(
+        mut,
+        token::mint = nft_mint,
+        token::authority = user,
+        constraint = nft_holding.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_holding: Option<Box<Account<'info, TokenAccount>>>,
+    /// The signer's index; proves they bought (or were gifted) the subscription once its NFT is burned
+    #[account(
+        seeds = [b"subscriber_index", plan.key().as_ref(), user.key().as_ref()],
+        bump = subscriber_index.bump
+    )]
+    pub subscriber_index: Option<Box<Account<'info, SubscriberIndex>>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = user
+    )]
+    pub user_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        constraint = user_subscription.refund_destination == Some(refund_token.owner) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives refunds on SOL plans when the subscription has a refund destination
+    #[account(
+        mut,
+        constraint = user_subscription.refund_destination == Some(refund_wallet.key()) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_wallet: Option<UncheckedAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives vested SOL on SOL plans
+    #[account(mut, address = plan.creator @ SolVeilErrors::Unauthorized)]
+    pub creator: Option<UncheckedAccount<'info>>,
+    pub token_program: Program<'info, Token>,
+    pub payment_token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreatorCancelSubscription<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        has_one = creator @ SolVeilErrors::Unauthorized
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(subscriber.key(), nft_mint.key()).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    /// CHECK: Current holder of the subscription NFT, proven by `nft_ata`; receives SOL refunds
+    #[account(mut)]
+    pub subscriber: UncheckedAccount<'info>,
+    #[account(
+        constraint = user_subscription.subscription_mint == nft_mint.key() @ SolVeilErrors::InvalidNftMint
+    )]
+    pub nft_mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        associated_token::mint = nft_mint,
+        associated_token::authority = subscriber,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+    /// Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub metadata: Box<Account<'info, anchor_spl::metadata::MetadataAccount>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        associated_token::mint = payment_mint,
+        associated_token::authority = subscriber,
+        associated_token::token_program = token_program
+    )]
+    pub subscriber_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        constraint = user_subscription.refund_destination == Some(refund_token.owner) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives refunds on SOL plans when the subscription has a refund destination
+    #[account(
+        mut,
+        constraint = user_subscription.refund_destination == Some(refund_wallet.key()) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_wallet: Option<UncheckedAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RenewSubscription<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = !plan.paused @ SolVeilErrors::PlanPaused,
+        constraint = plan.sunset_at == 0 @ SolVeilErrors::PlanSunset
+    )]
+    pub plan: Account<'info, SubscriptionPlan>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<InterfaceAccount<'info, PaymentTokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = user
+    )]
+    pub user_token: Option<InterfaceAccount<'info, PaymentTokenAccount>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
+        constraint = !user_subscription.trial @ SolVeilErrors::TrialNotConverted,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(user.key(), user_subscription.subscription_mint).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Account<'info, UserSubscription>,
+    #[account(
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = user,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Account<'info, TokenAccount>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<InterfaceAccount<'info, PaymentMint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EnableAutoRenewal<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = plan.payment_mint.is_some() @ SolVeilErrors::NativePlanUnsupported
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
+        constraint = !user_subscription.trial @ SolVeilErrors::TrialNotConverted,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(user.key(), user_subscription.subscription_mint).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = user,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+    /// The approval is per token account, so it can back only one subscription's auto-renewal
+    #[account(
+        mut,
+        token::authority = user,
+        constraint = plan.payment_mint == Some(user_token.mint) @ SolVeilErrors::InvalidPaymentMint,
+        constraint = Option::from(user_token.delegate) != Some(plan.key())
+            || (user_subscription.auto_renew_enabled
+                && user_subscription.auto_renew_token == user_token.key())
+            @ SolVeilErrors::AutoRenewalTokenInUse
+    )]
+    pub user_token: Box<InterfaceAccount<'info, PaymentTokenAccount>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct DisableAutoRenewal<'info> {
+    pub user: Signer<'info>,
     #[account(mut)]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        mut,
+        address = user_subscription.auto_renew_token @ SolVeilErrors::InvalidAutoRenewalAccount,
+        token::authority = user
+    )]
+    pub user_token: Box<InterfaceAccount<'info, PaymentTokenAccount>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ProcessAutoRenewal<'info> {
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = !plan.paused @ SolVeilErrors::PlanPaused,
+        constraint = plan.sunset_at == 0 @ SolVeilErrors::PlanSunset,
+        constraint = plan.payment_mint.is_some() @ SolVeilErrors::NativePlanUnsupported
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        mut,
+        address = user_subscription.auto_renew_token @ SolVeilErrors::InvalidAutoRenewalAccount
+    )]
+    pub user_token: Box<InterfaceAccount<'info, PaymentTokenAccount>>,
+    /// The payer must still hold the subscription NFT
+    #[account(
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = user_token.owner,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Box<InterfaceAccount<'info, PaymentMint>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Box<InterfaceAccount<'info, PaymentTokenAccount>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTokens<'info> {
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump
+    )]
+    pub plan: Account<'info, SubscriptionPlan>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
+    )]
+    pub user_subscription: Account<'info, UserSubscription>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<InterfaceAccount<'info, PaymentTokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<InterfaceAccount<'info, PaymentTokenAccount>>,
+    #[account(mut, signer, constraint = plan.creator == creator.key() @ SolVeilErrors::Unauthorized)]
     pub creator: Signer<'info>,
-    pub payment_mint: Account<'info, Mint>,
+    pub protocol_fee: ProtocolFee<'info>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<InterfaceAccount<'info, PaymentMint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
     pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimMany<'info> {
     #[account(
-        init,
-        payer = creator,
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump
+    )]
+    pub plan: Account<'info, SubscriptionPlan>,
+    #[account(
+        mut,
         seeds = [b"vault", plan.key().as_ref()],
-        bump,
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<InterfaceAccount<'info, PaymentTokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
         token::mint = payment_mint,
-        token::authority = plan
+        token::authority = plan.creator
     )]
-    pub vault: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
+    pub creator_token: Option<InterfaceAccount<'info, PaymentTokenAccount>>,
+    #[account(mut, constraint = plan.creator == creator.key() @ SolVeilErrors::Unauthorized)]
+    pub creator: Signer<'info>,
+    pub protocol_fee: ProtocolFee<'info>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<InterfaceAccount<'info, PaymentMint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct BuySubscription<'info> {
+pub struct UpdateProtocolConfig<'info> {
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub admin: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + ProtocolConfig::LEN,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+    #[account(constraint = this_program.programdata_address()? == Some(program_data.key()))]
+    pub this_program: Program<'info, program::Solvency>,
+    // Only the program's upgrade authority may change the protocol fee
+    #[account(constraint = program_data.upgrade_authority_address == Some(admin.key()) @ SolVeilErrors::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+/// The protocol config and the treasury accounts that receive its fee, shared by every
+/// instruction that charges it.
+#[derive(Accounts)]
+pub struct ProtocolFee<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// Only needed on token plans while a fee is charged; transfers check its mint
+    #[account(mut, constraint = treasury_token.owner == config.treasury @ SolVeilErrors::InvalidTreasury)]
+    pub treasury_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives the fee on SOL plans; only needed while a fee is charged
+    #[account(mut, address = config.treasury @ SolVeilErrors::InvalidTreasury)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+}
+
+impl<'info> ProtocolFee<'info> {
+    /// The treasury account matching the vault's payment mode.
+    fn treasury(&self, vault: &PlanVault<'_, 'info>) -> Result<AccountInfo<'info>> {
+        vault
+            .payment_account(
+                self.treasury_token.as_ref().map(|a| a.to_account_info()),
+                self.treasury.as_ref().map(|a| a.to_account_info()),
+            )
+            .map_err(|_| error!(SolVeilErrors::InvalidTreasury))
+    }
+}
+
+#[derive(Accounts)]
+pub struct ManagePlan<'info> {
+    pub creator: Signer<'info>,
     #[account(
+        mut,
         seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
-        bump = plan.bump
+        bump = plan.bump,
+        has_one = creator @ SolVeilErrors::Unauthorized
     )]
     pub plan: Box<Account<'info, SubscriptionPlan>>,
-    pub payment_mint: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePlan<'info> {
     #[account(mut)]
-    pub vault: Box<Account<'info, TokenAccount>>,
+    pub creator: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        has_one = creator @ SolVeilErrors::Unauthorized,
+        close = creator
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
     #[account(
         mut,
         token::mint = payment_mint,
-        token::authority = user,
-        constraint = payment_mint.key() == plan.payment_mint @ SolVeilErrors::InvalidPaymentMint
+        token::authority = plan.creator
+    )]
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeTier<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump,
+        constraint = !plan.paused @ SolVeilErrors::PlanPaused,
+        constraint = plan.sunset_at == 0 @ SolVeilErrors::PlanSunset
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = !user_subscription.trial @ SolVeilErrors::TrialNotConverted,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(user.key(), user_subscription.subscription_mint).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = user,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
     )]
-    pub user_token: Account<'info, TokenAccount>,
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        token::authority = user
+    )]
+    pub user_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = payment_mint,
+        constraint = user_subscription.refund_destination == Some(refund_token.owner) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives refunds on SOL plans when the subscription has a refund destination
+    #[account(
+        mut,
+        constraint = user_subscription.refund_destination == Some(refund_wallet.key()) @ SolVeilErrors::InvalidRefundDestination
+    )]
+    pub refund_wallet: Option<UncheckedAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
     #[account(
         mut,
         token::mint = payment_mint,
         token::authority = plan.creator
     )]
-    pub creator_token: Account<'info, TokenAccount>,
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives vested SOL on SOL plans
+    #[account(mut, address = plan.creator @ SolVeilErrors::Unauthorized)]
+    pub creator: Option<UncheckedAccount<'info>>,
+    /// Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub metadata: Box<Account<'info, anchor_spl::metadata::MetadataAccount>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AssertActiveSubscription<'info> {
+    #[account(
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    /// Seat pools only grant access through `assert_active_seat`, so buying N seats gives N
+    /// holders access rather than N plus the admin
+    #[account(
+        has_one = plan,
+        constraint = user_subscription.seats == 0 @ SolVeilErrors::SeatPoolNotASubscription,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(holder.key(), user_subscription.subscription_mint).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    /// CHECK: Any wallet or PDA; ownership is proven by `nft_ata`
+    pub holder: UncheckedAccount<'info>,
+    #[account(
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = holder,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct AssertActiveSeat<'info> {
+    #[account(
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    /// The seat pool
+    #[account(has_one = plan)]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        seeds = [b"seat", user_subscription.key().as_ref(), seat.member.as_ref()],
+        bump = seat.bump
+    )]
+    pub seat: Box<Account<'info, SeatAssignment>>,
+    /// CHECK: Any wallet or PDA; ownership is proven by `nft_ata`
+    #[account(constraint = plan.transferable || holder.key() == seat.member @ SolVeilErrors::Unauthorized)]
+    pub holder: UncheckedAccount<'info>,
+    #[account(
+        associated_token::mint = seat.nft_mint,
+        associated_token::authority = holder,
+        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct AssignSeat<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    /// CHECK: Any wallet; must also sign when the plan is not transferable
+    #[account(constraint = plan.transferable || member.is_signer @ SolVeilErrors::RecipientMustSign)]
+    pub member: UncheckedAccount<'info>,
+    #[account(
+        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
+        bump = plan.bump
+    )]
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
+    #[account(
+        mut,
+        has_one = plan,
+        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
+        constraint = user_subscription.seats > 0 @ SolVeilErrors::NotASeatPool,
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(admin.key(), user_subscription.subscription_mint).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
+    )]
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = admin,
+        constraint = admin_nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+    )]
+    pub admin_nft_ata: Box<Account<'info, TokenAccount>>,
     #[account(
         init,
-        payer = user,
-        space = 8 + UserSubscription::LEN,
-        seeds = [b"user_subscription", plan.key().as_ref(), user.key().as_ref()],
+        payer = admin,
+        space = 8 + SeatAssignment::LEN,
+        seeds = [b"seat", user_subscription.key().as_ref(), member.key().as_ref()],
         bump
     )]
-pub user_subscription: Box<Account<'info, UserSubscription>>,
+    pub seat: Box<Account<'info, SeatAssignment>>,
     #[account(
         init,
-        payer = user,
+        payer = admin,
         mint::decimals = 0,
-        mint::authority = user_subscription,
-        mint::freeze_authority = user_subscription
+        mint::authority = seat,
+        mint::freeze_authority = seat
     )]
-pub nft_mint: Box<Account<'info, Mint>>,
+    pub nft_mint: Box<Account<'info, Mint>>,
     #[account(
         init,
-        payer = user,
+        payer = admin,
         associated_token::mint = nft_mint,
-        associated_token::authority = user
+        associated_token::authority = member
     )]
-    pub nft_ata: Account<'info, TokenAccount>,
+    pub nft_ata: Box<Account<'info, TokenAccount>>,
     /// CHECK: Verified in code against MetadataAccount::find_pda
     #[account(mut)]
     pub metadata: UncheckedAccount<'info>,
     /// CHECK: Verified in code against MasterEdition::find_pda
     #[account(mut)]
     pub master_edition: UncheckedAccount<'info>,
+    #[account(
+        address = plan.nft_collection @ SolVeilErrors::InvalidCollection
+    )]
+    pub collection_mint: Box<Account<'info, Mint>>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    #[account(mut)]
+    pub collection_metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    pub collection_master_edition: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
@@ -447,9 +3026,9 @@ pub nft_mint: Box<Account<'info, Mint>>,
 }
 
 #[derive(Accounts)]
-pub struct CloseSubscription<'info> {
+pub struct RevokeSeat<'info> {
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub admin: Signer<'info>,
     #[account(
         seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
         bump = plan.bump
@@ -458,125 +3037,891 @@ pub struct CloseSubscription<'info> {
     #[account(
         mut,
         has_one = plan,
-        seeds = [b"user_subscription", plan.key().as_ref(), user.key().as_ref()],
-        bump = user_subscription.bump,
-        close = user
+        seeds = [
+            b"user_subscription",
+            plan.key().as_ref(),
+            plan.subscription_seed(admin.key(), user_subscription.subscription_mint).as_ref(),
+            &user_subscription.index.to_le_bytes()
+        ],
+        bump = user_subscription.bump
     )]
     pub user_subscription: Box<Account<'info, UserSubscription>>,
-    #[account(mut)]
-    pub vault: Box<Account<'info, TokenAccount>>,
     #[account(
-        mut,
-        token::mint = plan.payment_mint,
-        token::authority = user
-    )]
-    pub user_token: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        token::mint = plan.payment_mint,
-        token::authority = plan.creator
+        associated_token::mint = user_subscription.subscription_mint,
+        associated_token::authority = admin,
+        constraint = admin_nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
     )]
-    pub creator_token: Account<'info, TokenAccount>,
+    pub admin_nft_ata: Box<Account<'info, TokenAccount>>,
     #[account(
         mut,
-        constraint = user_subscription.subscription_mint == nft_mint.key() @ SolVeilErrors::InvalidNftMint
+        seeds = [b"seat", user_subscription.key().as_ref(), seat.member.as_ref()],
+        bump = seat.bump,
+        close = admin
     )]
-    pub nft_mint: Account<'info, Mint>,
+    pub seat: Box<Account<'info, SeatAssignment>>,
+    #[account(mut, address = seat.nft_mint @ SolVeilErrors::InvalidNftMint)]
+    pub nft_mint: Box<Account<'info, Mint>>,
+    /// The member's seat NFT account; omitted once the NFT has left it
     #[account(
         mut,
         associated_token::mint = nft_mint,
-        associated_token::authority = user,
-        constraint = nft_ata.amount == 1 @ SolVeilErrors::InvalidNftAmount
+        associated_token::authority = seat.member
     )]
-    pub nft_ata: Account<'info, TokenAccount>,
+    pub seat_nft_ata: Option<Box<Account<'info, TokenAccount>>>,
+    /// CHECK: Verified in code against MetadataAccount::find_pda
+    pub metadata: UncheckedAccount<'info>,
+    /// CHECK: Verified in code against MasterEdition::find_pda
+    pub master_edition: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+    pub token_metadata_program: Program<'info, anchor_spl::metadata::Metadata>,
 }
 
-#[derive(Accounts)]
-pub struct RenewSubscription<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
-    #[account(
-        seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
-        bump = plan.bump
-    )]
-    pub plan: Account<'info, SubscriptionPlan>,
-    #[account(mut)]
-    pub vault: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        token::mint = plan.payment_mint,
-        token::authority = user
-    )]
-    pub user_token: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        has_one = plan,
-        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
-        seeds = [b"user_subscription", plan.key().as_ref(), user.key().as_ref()],
-        bump = user_subscription.bump
-    )]
-    pub user_subscription: Account<'info, UserSubscription>,
-    pub payment_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SubscriptionStatus {
+    pub plan: Pubkey,
+    pub holder: Pubkey,
+    pub subscription_mint: Pubkey,
+    pub tier: u8,
+    pub expires_at: u64,
+    pub unvested_amount: u64,
 }
 
 #[derive(Accounts)]
-pub struct ClaimTokens<'info> {
+pub struct ExpireSubscription<'info> {
+    /// Still allowed after a sunset for subscriptions that fully vested before it
     #[account(
+        mut,
         seeds = [b"plan", plan.creator.as_ref(), plan.seed.as_ref()],
         bump = plan.bump
     )]
-    pub plan: Account<'info, SubscriptionPlan>,
+    pub plan: Box<Account<'info, SubscriptionPlan>>,
     #[account(
         mut,
         has_one = plan,
-        constraint = user_subscription.is_active @ SolVeilErrors::SubscriptionNotActive,
     )]
-    pub user_subscription: Account<'info, UserSubscription>,
-    #[account(mut)]
-    pub vault: Account<'info, TokenAccount>,
+    pub user_subscription: Box<Account<'info, UserSubscription>>,
+    #[account(
+        constraint = plan.payment_mint == Some(payment_mint.key()) @ SolVeilErrors::InvalidPaymentMint
+    )]
+    pub payment_mint: Option<Box<InterfaceAccount<'info, PaymentMint>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    #[account(
+        mut,
+        seeds = [b"vault", plan.key().as_ref()],
+        bump = plan.vault_bump
+    )]
+    pub sol_vault: Option<SystemAccount<'info>>,
+    pub protocol_fee: ProtocolFee<'info>,
     #[account(
         mut,
-        token::mint = plan.payment_mint,
+        token::mint = payment_mint,
         token::authority = plan.creator
     )]
-    pub creator_token: Account<'info, TokenAccount>,
-    #[account(mut, signer, constraint = plan.creator == creator.key() @ SolVeilErrors::Unauthorized)]
-    pub creator: Signer<'info>,
-    pub payment_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
+    pub creator_token: Option<Box<InterfaceAccount<'info, PaymentTokenAccount>>>,
+    /// CHECK: Receives vested SOL on SOL plans
+    #[account(mut, address = plan.creator @ SolVeilErrors::Unauthorized)]
+    pub creator: Option<UncheckedAccount<'info>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Plan settings passed to `create_plan`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct CreatePlanArgs {
+    pub tiers: Vec<SubscriptionTier>,
+    pub vesting_schedule: VestingSchedule,
+    /// One-time cliff from a subscription's first payment, see `SubscriptionPlan::cliff_duration`.
+    /// Only valid with `VestingSchedule::Linear`
+    pub cliff_duration: u64,
+    /// 0 disables free trials
+    pub trial_duration: u64,
+    pub transferable: bool,
+    pub grace_period: u64,
+    pub collection_name: String,
+    pub collection_symbol: String,
+    pub collection_uri: String,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SubscriptionTier {
+    pub price: u64,
+    pub vesting_duration: u64,
+    pub upfront_percentage: u8,
+    pub uri: String,
+}
+
+impl SubscriptionTier {
+    const LEN: usize = 8 + 8 + 1 + 4 + MAX_URI_LEN;
+
+    /// This tier priced for a pool of `seats` seats; 0 means an individual subscription.
+    pub fn for_seats(&self, seats: u16) -> Result<SubscriptionTier> {
+        let price = self
+            .price
+            .checked_mul(seats.max(1) as u64)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        Ok(SubscriptionTier { price, ..self.clone() })
+    }
+
+    /// Length of access that `amount` buys at this tier's rate.
+    pub fn period_for(&self, amount: u64) -> u64 {
+        ((amount as u128 * self.vesting_duration as u128) / self.price as u128) as u64
+    }
 }
 
 #[account]
 pub struct SubscriptionPlan {
     pub creator: Pubkey,
-    pub upfront_percentage: u8,
-    pub vesting_duration: u64,
-    pub payment_mint: Pubkey,
+    /// `None` for plans priced in SOL
+    pub payment_mint: Option<Pubkey>,
     pub nft_collection: Pubkey,
+    pub transferable: bool,
+    pub grace_period: u64,
     pub creation_timestamp: u64,
     pub bump: u8,
+    pub vault_bump: u8,
     pub seed: Vec<u8>,
+    pub tiers: Vec<SubscriptionTier>,
+    pub active_subscribers: u64,
+    pub total_deposited: u64,
+    pub total_claimed_by_creator: u64,
+    pub total_refunded: u64,
+    pub paused: bool,
+    /// Time vesting stops for every subscription; 0 while the plan is live
+    pub sunset_at: u64,
+    pub vesting_schedule: VestingSchedule,
+    /// Seconds after a subscription is first paid for before anything beyond upfront shares
+    /// vests. Unlike `VestingSchedule::CliffLinear`, which restarts with every tranche, this runs
+    /// once per subscription, so renewals and tier changes don't push it back. Only set on
+    /// `Linear` plans
+    pub cliff_duration: u64,
+    /// Length of a free trial; 0 when the plan offers none
+    pub trial_duration: u64,
 }
 
 impl SubscriptionPlan {
-    const LEN: usize = 32 + 1 + 8 + 32 + 32 + 8 + 1 + 4 + 32;
+    const LEN: usize =
+        32 + 1 + 32 + 32 + 1 + 8 + 8 + 1 + 1 + 4 + 32 + 4 + MAX_TIERS * SubscriptionTier::LEN
+            + 8 + 8 + 8 + 8 + 1 + 8 + VestingSchedule::LEN + 8 + 8;
+
+    pub fn tier(&self, index: u8) -> Result<&SubscriptionTier> {
+        self.tiers
+            .get(index as usize)
+            .ok_or(error!(SolVeilErrors::InvalidTier))
+    }
+
+    /// Clock used for vesting: frozen at the sunset time once the plan has been wound down.
+    pub fn accrual_time(&self, now: u64) -> u64 {
+        if self.sunset_at == 0 {
+            now
+        } else {
+            now.min(self.sunset_at)
+        }
+    }
+
+    /// Keeps the plan-wide totals in step with money moving in and out of subscriptions.
+    pub fn record_flows(&mut self, deposited: u64, claimed: u64, refunded: u64) -> Result<()> {
+        self.total_deposited = self
+            .total_deposited
+            .checked_add(deposited)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        self.total_claimed_by_creator = self
+            .total_claimed_by_creator
+            .checked_add(claimed)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        self.total_refunded = self
+            .total_refunded
+            .checked_add(refunded)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Key that seeds a `UserSubscription`: the NFT mint for transferable plans, so the
+    /// subscription follows the token, otherwise the subscriber's wallet.
+    pub fn subscription_seed(&self, user: Pubkey, nft_mint: Pubkey) -> Pubkey {
+        if self.transferable {
+            nft_mint
+        } else {
+            user
+        }
+    }
+}
+
+/// Protocol-wide fee settings, a singleton at `[b"config"]`.
+#[account]
+pub struct ProtocolConfig {
+    /// Share of upfront payments and creator payouts sent to the treasury
+    pub fee_bps: u16,
+    pub treasury: Pubkey,
+    pub bump: u8,
+}
+
+impl ProtocolConfig {
+    const LEN: usize = 2 + 32 + 1;
+
+    pub fn fee_on(&self, amount: u64) -> u64 {
+        ((amount as u128 * self.fee_bps as u128) / 10_000) as u64
+    }
+}
+
+/// Where a plan keeps its deposits: a token vault, or for SOL plans a system-owned PDA at the
+/// same `[b"vault", plan]` address.
+pub enum PlanVault<'a, 'info> {
+    Token {
+        vault: &'a mut InterfaceAccount<'info, PaymentTokenAccount>,
+        mint: &'a InterfaceAccount<'info, PaymentMint>,
+        token_program: AccountInfo<'info>,
+    },
+    Native {
+        vault: AccountInfo<'info>,
+        system_program: AccountInfo<'info>,
+    },
+}
+
+impl<'a, 'info> PlanVault<'a, 'info> {
+    /// Picks the vault matching the plan's payment mode out of an instruction's optional accounts.
+    pub fn new(
+        plan: &SubscriptionPlan,
+        vault: Option<&'a mut InterfaceAccount<'info, PaymentTokenAccount>>,
+        mint: Option<&'a InterfaceAccount<'info, PaymentMint>>,
+        token_program: Option<&Interface<'info, TokenInterface>>,
+        sol_vault: Option<&SystemAccount<'info>>,
+        system_program: &Program<'info, System>,
+    ) -> Result<Self> {
+        match (plan.payment_mint, vault, mint, token_program, sol_vault) {
+            (Some(_), Some(vault), Some(mint), Some(token_program), None) => Ok(Self::Token {
+                vault,
+                mint,
+                token_program: token_program.to_account_info(),
+            }),
+            (None, None, None, None, Some(sol_vault)) => Ok(Self::Native {
+                vault: sol_vault.to_account_info(),
+                system_program: system_program.to_account_info(),
+            }),
+            _ => err!(SolVeilErrors::InvalidPaymentAccounts),
+        }
+    }
+
+    /// Chooses the token account on token plans and the wallet on SOL plans.
+    pub fn payment_account(
+        &self,
+        token_account: Option<AccountInfo<'info>>,
+        wallet: Option<AccountInfo<'info>>,
+    ) -> Result<AccountInfo<'info>> {
+        match self {
+            Self::Token { .. } => token_account,
+            Self::Native { .. } => wallet,
+        }
+        .ok_or(error!(SolVeilErrors::InvalidPaymentAccounts))
+    }
+
+    pub fn balance(&self) -> u64 {
+        match self {
+            Self::Token { vault, .. } => vault.amount,
+            Self::Native { vault, .. } => vault.lamports(),
+        }
+    }
+
+    /// Moves `amount` of the plan's payment asset; on SOL plans `from` itself must sign.
+    pub fn transfer(
+        &self,
+        from: AccountInfo<'info>,
+        to: AccountInfo<'info>,
+        authority: AccountInfo<'info>,
+        signer_seeds: &[&[&[u8]]],
+        amount: u64,
+    ) -> Result<()> {
+        match self {
+            Self::Token { mint, token_program, .. } => token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program.clone(),
+                    TransferChecked {
+                        from,
+                        mint: mint.to_account_info(),
+                        to,
+                        authority,
+                    },
+                    signer_seeds,
+                ),
+                amount,
+                mint.decimals,
+            ),
+            Self::Native { system_program, .. } => system_program::transfer(
+                CpiContext::new_with_signer(
+                    system_program.clone(),
+                    system_program::Transfer { from, to },
+                    signer_seeds,
+                ),
+                amount,
+            ),
+        }
+    }
+
+    /// Pays into the vault and returns what it actually received, net of any transfer fee.
+    pub fn deposit(
+        &mut self,
+        from: AccountInfo<'info>,
+        authority: AccountInfo<'info>,
+        signer_seeds: &[&[&[u8]]],
+        amount: u64,
+    ) -> Result<u64> {
+        let before = self.balance();
+        let vault = match self {
+            Self::Token { vault, .. } => vault.to_account_info(),
+            Self::Native { vault, .. } => vault.clone(),
+        };
+        self.transfer(from, vault, authority, signer_seeds, amount)?;
+        if let Self::Token { vault, .. } = self {
+            vault.reload()?;
+        }
+        self.balance()
+            .checked_sub(before)
+            .ok_or(error!(SolVeilErrors::MathOverflow))
+    }
+
+    /// Pays out of the vault, signed by the plan PDA (token vault) or the vault PDA itself.
+    pub fn withdraw(
+        &self,
+        plan: &Account<'info, SubscriptionPlan>,
+        to: AccountInfo<'info>,
+        amount: u64,
+    ) -> Result<()> {
+        match self {
+            Self::Token { vault, .. } => {
+                let plan_seeds_inner: &[&[u8]] = &[
+                    b"plan",
+                    plan.creator.as_ref(),
+                    &plan.seed,
+                    &[plan.bump],
+                ];
+                self.transfer(
+                    vault.to_account_info(),
+                    to,
+                    plan.to_account_info(),
+                    &[plan_seeds_inner],
+                    amount,
+                )
+            }
+            Self::Native { vault, .. } => {
+                let plan_key = plan.key();
+                let vault_seeds_inner: &[&[u8]] = &[
+                    b"vault",
+                    plan_key.as_ref(),
+                    &[plan.vault_bump],
+                ];
+                self.transfer(
+                    vault.clone(),
+                    to,
+                    vault.clone(),
+                    &[vault_seeds_inner],
+                    amount,
+                )
+            }
+        }
+    }
+
+    /// Pays `amount` of vested funds out of the vault to the creator, less the protocol fee
+    /// which goes to the treasury. Every payout to the creator goes through here.
+    pub fn pay_creator(
+        &self,
+        plan: &Account<'info, SubscriptionPlan>,
+        fee: &ProtocolFee<'info>,
+        creator: AccountInfo<'info>,
+        amount: u64,
+    ) -> Result<u64> {
+        let protocol_fee = fee.config.fee_on(amount);
+        if protocol_fee > 0 {
+            self.withdraw(plan, fee.treasury(self)?, protocol_fee)?;
+        }
+        self.withdraw(plan, creator, amount - protocol_fee)?;
+        Ok(protocol_fee)
+    }
+
+    /// Takes a tier's price from `from`: the protocol fee and the creator's upfront share are
+    /// paid out directly and the rest goes into the vault.
+    pub fn collect_purchase(
+        &mut self,
+        fee: &ProtocolFee<'info>,
+        tier: &SubscriptionTier,
+        from: AccountInfo<'info>,
+        authority: AccountInfo<'info>,
+        creator: AccountInfo<'info>,
+    ) -> Result<PurchasePayment> {
+        let upfront = ((tier.upfront_percentage as u128 * tier.price as u128) / 100) as u64;
+        let remaining = tier.price.saturating_sub(upfront);
+
+        // The protocol fee comes out of the upfront share
+        let protocol_fee = fee.config.fee_on(upfront);
+        if protocol_fee > 0 {
+            self.transfer(from.clone(), fee.treasury(self)?, authority.clone(), &[], protocol_fee)?;
+        }
+
+        // Transfer upfront to creator if any
+        let creator_upfront = upfront - protocol_fee;
+        if creator_upfront > 0 {
+            self.transfer(from.clone(), creator, authority.clone(), &[], creator_upfront)?;
+        }
+
+        // Transfer remaining to vault; transfer-fee mints deliver less than was sent,
+        // so the deposit is whatever actually landed in the vault
+        let received = if remaining > 0 {
+            self.deposit(from, authority, &[], remaining)?
+        } else {
+            0
+        };
+
+        Ok(PurchasePayment {
+            upfront,
+            protocol_fee,
+            received,
+        })
+    }
+
+    /// Sweeps whatever is left to `to` and closes the vault, returning its rent to
+    /// `rent_destination`.
+    pub fn close(
+        &self,
+        plan: &Account<'info, SubscriptionPlan>,
+        to: AccountInfo<'info>,
+        rent_destination: AccountInfo<'info>,
+    ) -> Result<()> {
+        let balance = self.balance();
+        match self {
+            Self::Token { vault, token_program, .. } => {
+                if balance > 0 {
+                    self.withdraw(plan, to, balance)?;
+                }
+                let plan_seeds_inner: &[&[u8]] = &[
+                    b"plan",
+                    plan.creator.as_ref(),
+                    &plan.seed,
+                    &[plan.bump],
+                ];
+                token_interface::close_account(CpiContext::new_with_signer(
+                    token_program.clone(),
+                    token_interface::CloseAccount {
+                        account: vault.to_account_info(),
+                        destination: rent_destination,
+                        authority: plan.to_account_info(),
+                    },
+                    &[plan_seeds_inner],
+                ))
+            }
+            // The lamport vault holds nothing but its balance, so draining it closes it
+            Self::Native { .. } => self.withdraw(plan, to, balance),
+        }
+    }
+}
+
+/// How a tier purchase was split by `PlanVault::collect_purchase`.
+pub struct PurchasePayment {
+    /// Paid straight out, protocol fee included
+    pub upfront: u64,
+    pub protocol_fee: u64,
+    /// What actually reached the vault
+    pub received: u64,
+}
+
+impl PurchasePayment {
+    /// Amount the subscription's tranche is recorded at.
+    pub fn deposit(&self) -> Result<u64> {
+        self.upfront
+            .checked_add(self.received)
+            .ok_or(error!(SolVeilErrors::MathOverflow))
+    }
+}
+
+/// Accounts needed to mint a subscription NFT into a plan's collection.
+pub struct SubscriptionNft<'info> {
+    pub payer: AccountInfo<'info>,
+    /// Wallet that owns `nft_ata`; signs the delegation on non-transferable plans
+    pub owner: AccountInfo<'info>,
+    pub user_subscription: AccountInfo<'info>,
+    pub nft_mint: AccountInfo<'info>,
+    pub nft_ata: AccountInfo<'info>,
+    pub metadata: AccountInfo<'info>,
+    pub master_edition: AccountInfo<'info>,
+    pub collection_mint: AccountInfo<'info>,
+    pub collection_metadata: AccountInfo<'info>,
+    pub collection_master_edition: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    pub token_metadata_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub rent: AccountInfo<'info>,
+}
+
+impl<'info> SubscriptionNft<'info> {
+    /// Mints the NFT to `owner`, verifies it in the plan collection and, unless the plan is
+    /// transferable, freezes it under the subscription PDA signing with `sub_seeds`.
+    pub fn mint(
+        &self,
+        plan: &Account<'info, SubscriptionPlan>,
+        sub_seeds: &[&[&[u8]]],
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        // Verify metadata and edition PDAs
+        let (metadata_pda, _) = MetadataAccount::find_pda(&self.nft_mint.key());
+        require_keys_eq!(metadata_pda, self.metadata.key());
+        let (edition_pda, _) = MasterEdition::find_pda(&self.nft_mint.key());
+        require_keys_eq!(edition_pda, self.master_edition.key());
+
+        token::mint_to(
+            CpiContext::new_with_signer(
+                self.token_program.clone(),
+                MintTo {
+                    mint: self.nft_mint.clone(),
+                    to: self.nft_ata.clone(),
+                    authority: self.user_subscription.clone(),
+                },
+                sub_seeds,
+            ),
+            1,
+        )?;
+
+        // Create metadata
+        let data_v2 = DataV2 {
+            name,
+            symbol,
+            uri,
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: Some(Collection {
+                verified: false,
+                key: plan.nft_collection,
+            }),
+            uses: None,
+        };
+
+        create_metadata_accounts_v3(
+            CpiContext::new_with_signer(
+                self.token_metadata_program.clone(),
+                CreateMetadataAccountsV3 {
+                    metadata: self.metadata.clone(),
+                    mint: self.nft_mint.clone(),
+                    mint_authority: self.user_subscription.clone(),
+                    payer: self.payer.clone(),
+                    update_authority: self.user_subscription.clone(),
+                    system_program: self.system_program.clone(),
+                    rent: self.rent.clone(),
+                },
+                sub_seeds,
+            ),
+            data_v2,
+            true,
+            true,
+            None,
+        )?;
+
+        // Create master edition
+        create_master_edition_v3(
+            CpiContext::new_with_signer(
+                self.token_metadata_program.clone(),
+                CreateMasterEditionV3 {
+                    edition: self.master_edition.clone(),
+                    mint: self.nft_mint.clone(),
+                    update_authority: self.user_subscription.clone(),
+                    mint_authority: self.user_subscription.clone(),
+                    payer: self.payer.clone(),
+                    metadata: self.metadata.clone(),
+                    token_program: self.token_program.clone(),
+                    system_program: self.system_program.clone(),
+                    rent: self.rent.clone(),
+                },
+                sub_seeds,
+            ),
+            Some(0),
+        )?;
+
+        // Verify the NFT as a member of the plan's sized collection
+        let (collection_metadata_pda, _) = MetadataAccount::find_pda(&plan.nft_collection);
+        require_keys_eq!(collection_metadata_pda, self.collection_metadata.key());
+        let (collection_edition_pda, _) = MasterEdition::find_pda(&plan.nft_collection);
+        require_keys_eq!(collection_edition_pda, self.collection_master_edition.key());
+
+        let plan_seeds_inner: &[&[u8]] = &[
+            b"plan",
+            plan.creator.as_ref(),
+            &plan.seed,
+            &[plan.bump],
+        ];
+        let plan_seeds: &[&[&[u8]]] = &[plan_seeds_inner];
+
+        verify_sized_collection_item(
+            CpiContext::new_with_signer(
+                self.token_metadata_program.clone(),
+                VerifySizedCollectionItem {
+                    payer: self.payer.clone(),
+                    metadata: self.metadata.clone(),
+                    collection_authority: plan.to_account_info(),
+                    collection_mint: self.collection_mint.clone(),
+                    collection_metadata: self.collection_metadata.clone(),
+                    collection_master_edition: self.collection_master_edition.clone(),
+                },
+                plan_seeds,
+            ),
+            None,
+        )?;
+
+        // Lock the NFT to the subscriber: delegate it to the subscription PDA and freeze it
+        // through the master edition, which now holds the mint's freeze authority
+        if !plan.transferable {
+            token::approve(
+                CpiContext::new(
+                    self.token_program.clone(),
+                    Approve {
+                        to: self.nft_ata.clone(),
+                        delegate: self.user_subscription.clone(),
+                        authority: self.owner.clone(),
+                    },
+                ),
+                1,
+            )?;
+
+            freeze_delegated_account(CpiContext::new_with_signer(
+                self.token_metadata_program.clone(),
+                FreezeDelegatedAccount {
+                    metadata: self.metadata.clone(),
+                    delegate: self.user_subscription.clone(),
+                    token_account: self.nft_ata.clone(),
+                    edition: self.master_edition.clone(),
+                    mint: self.nft_mint.clone(),
+                    token_program: self.token_program.clone(),
+                },
+                sub_seeds,
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Hands out the index for each new subscription a wallet takes on a plan and lists every
+/// subscription opened so far, so a wallet can hold several at once and closed ones stay
+/// findable as history. `subscriptions[i]` is the UserSubscription created with index `i`.
+#[account]
+pub struct SubscriberIndex {
+    pub plan: Pubkey,
+    pub subscriber: Pubkey,
+    pub next_index: u32,
+    pub bump: u8,
+    pub subscriptions: Vec<Pubkey>,
+}
+
+impl SubscriberIndex {
+    /// Size with an empty `subscriptions` list; the account grows by 32 bytes per entry.
+    const LEN: usize = 32 + 32 + 4 + 1 + 4;
+
+    /// Consumes `next_index` for a newly created subscription and appends it to the list,
+    /// reallocating the account with `payer` covering the extra rent.
+    pub fn record<'info>(
+        index: &mut Account<'info, SubscriberIndex>,
+        plan: Pubkey,
+        subscriber: Pubkey,
+        bump: u8,
+        subscription: Pubkey,
+        payer: AccountInfo<'info>,
+        system_program: AccountInfo<'info>,
+    ) -> Result<()> {
+        let info = index.to_account_info();
+        let new_len = 8 + Self::LEN + (index.subscriptions.len() + 1) * 32;
+        let rent_due = Rent::get()?.minimum_balance(new_len).saturating_sub(info.lamports());
+        if rent_due > 0 {
+            system_program::transfer(
+                CpiContext::new(system_program, system_program::Transfer { from: payer, to: info.clone() }),
+                rent_due,
+            )?;
+        }
+        info.resize(new_len)?;
+
+        index.plan = plan;
+        index.subscriber = subscriber;
+        index.bump = bump;
+        index.subscriptions.push(subscription);
+        index.next_index = index
+            .next_index
+            .checked_add(1)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        Ok(())
+    }
+}
+
+/// A seat of a seat pool held by a member wallet; closed when the seat is revoked.
+#[account]
+pub struct SeatAssignment {
+    /// The seat pool's UserSubscription
+    pub pool: Pubkey,
+    pub member: Pubkey,
+    pub nft_mint: Pubkey,
+    pub assigned_at: u64,
+    pub bump: u8,
+}
+
+impl SeatAssignment {
+    const LEN: usize = 32 + 32 + 32 + 8 + 1;
+}
+
+/// Marks that a wallet has used its trial on a plan; never closed, so the trial can't repeat.
+#[account]
+pub struct TrialRecord {
+    pub plan: Pubkey,
+    pub user: Pubkey,
+    pub started_at: u64,
+    pub bump: u8,
+}
+
+impl TrialRecord {
+    const LEN: usize = 32 + 32 + 8 + 1;
 }
 
 #[account]
 pub struct UserSubscription {
     pub plan: Pubkey,
     pub subscription_mint: Pubkey,
+    pub tier: u8,
     pub start_time: u64,
+    pub expires_at: u64,
     pub total_deposit_amount: u64,
     pub claimed_by_creator_amount: u64,
     pub refund_token_amount: u64,
     pub is_active: bool,
     pub bump: u8,
+    pub settled_amount: u64,
+    pub tranches: Vec<VestingTranche>,
+    pub auto_renew_enabled: bool,
+    pub auto_renew_token: Pubkey,
+    pub auto_renew_cap: u64,
+    pub auto_renew_spent: u64,
+    /// Free trial that has not been paid for yet
+    pub trial: bool,
+    /// Position among the subscriber's subscriptions to the plan; part of the PDA seeds
+    pub index: u32,
+    /// When the subscription was closed, cancelled or expired; 0 while it is live
+    pub closed_at: u64,
+    /// Receives refunds instead of the subscriber, e.g. the payer of a gift. Cleared once the
+    /// subscriber adds funds of their own by renewing or upgrading
+    pub refund_destination: Option<Pubkey>,
+    /// Seats bought with `buy_seats`; 0 for an individual subscription
+    pub seats: u16,
+    /// Seats currently assigned to member wallets
+    pub assigned_seats: u16,
+    /// End of the plan's cliff, counted from the first payment; until then only upfront shares
+    /// vest
+    pub cliff_end: u64,
 }
 
 impl UserSubscription {
-    const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 1 + 1;
+    const LEN: usize =
+        32 + 32 + 1 + 8 + 8 + 8 + 8 + 8 + 1 + 1 + 8 + 4 + MAX_TRANCHES * VestingTranche::LEN
+            + 1 + 32 + 8 + 8 + 1 + 4 + 8 + 33 + 2 + 2 + 8;
+
+    /// True once the paid period and the plan's grace window have both elapsed.
+    pub fn is_expired(&self, plan: &SubscriptionPlan, now: u64) -> bool {
+        now >= self.expires_at.saturating_add(plan.grace_period)
+    }
+
+    /// Status of a subscription that is active, unexpired and on a plan that has not sunset,
+    /// with at least `min_unvested_amount` still unvested.
+    pub fn active_status(
+        &self,
+        plan: &Account<SubscriptionPlan>,
+        holder: Pubkey,
+        min_unvested_amount: Option<u64>,
+        now: u64,
+    ) -> Result<SubscriptionStatus> {
+        require!(self.is_active, SolVeilErrors::SubscriptionNotActive);
+        require!(!self.is_expired(plan, now), SolVeilErrors::SubscriptionExpired);
+        require!(
+            plan.sunset_at == 0 || now < plan.sunset_at,
+            SolVeilErrors::PlanSunset
+        );
+
+        let vested = self.vested_amount(plan, now);
+        let unvested = self.total_deposit_amount.saturating_sub(vested);
+        if let Some(min_unvested_amount) = min_unvested_amount {
+            require!(
+                unvested >= min_unvested_amount,
+                SolVeilErrors::InsufficientUnvestedBalance
+            );
+        }
+
+        Ok(SubscriptionStatus {
+            plan: plan.key(),
+            holder,
+            subscription_mint: self.subscription_mint,
+            tier: self.tier,
+            expires_at: self.expires_at,
+            unvested_amount: unvested,
+        })
+    }
+
+    /// Deactivates a settled subscription, keeping what went to each side as history.
+    pub fn archive(&mut self, vested: u64, refund: u64, now: u64) {
+        self.claimed_by_creator_amount = vested;
+        self.refund_token_amount = refund;
+        self.is_active = false;
+        self.closed_at = now;
+    }
+
+    /// Deposits from fully vested tranches that were folded away plus every live tranche's vested
+    /// amount under the plan's schedule, stopping at the plan's sunset. Tranches running when
+    /// the plan's cliff ends only vest their upfront share before `cliff_end`.
+    pub fn vested_amount(&self, plan: &SubscriptionPlan, now: u64) -> u64 {
+        let now = plan.accrual_time(now);
+        self.tranches.iter().fold(self.settled_amount, |vested, tranche| {
+            let cliff = self.cliff_end.saturating_sub(tranche.start_time);
+            vested.saturating_add(tranche.vested_at(&plan.vesting_schedule, cliff, now))
+        })
+    }
+
+    /// Queues a renewal payment as its own tranche, starting when the current paid period ends.
+    /// Access is priced on `paid`; the tranche only holds what the vault `received`. The holder
+    /// pays for renewals, so refunds go back to them from then on.
+    pub fn renew(
+        &mut self,
+        tier: &SubscriptionTier,
+        paid: u64,
+        received: u64,
+        now: u64,
+    ) -> Result<()> {
+        let tranche = VestingTranche {
+            start_time: self.expires_at.max(now),
+            duration: tier.period_for(paid),
+            amount: received,
+            upfront_amount: ((tier.upfront_percentage as u128 * received as u128) / 100) as u64,
+        };
+        self.refund_destination = None;
+        self.add_tranche(tranche, now)
+    }
+
+    /// Records a new deposit, folding fully vested tranches into `settled_amount` to make room.
+    pub fn add_tranche(&mut self, tranche: VestingTranche, now: u64) -> Result<()> {
+        if self.tranches.len() >= MAX_TRANCHES {
+            let settled: u64 = self
+                .tranches
+                .iter()
+                .filter(|t| t.end_time() <= now)
+                .map(|t| t.amount)
+                .sum();
+            self.tranches.retain(|t| t.end_time() > now);
+            self.settled_amount = self
+                .settled_amount
+                .checked_add(settled)
+                .ok_or(SolVeilErrors::MathOverflow)?;
+        }
+        require!(self.tranches.len() < MAX_TRANCHES, SolVeilErrors::TooManyTranches);
+
+        self.total_deposit_amount = self
+            .total_deposit_amount
+            .checked_add(tranche.amount)
+            .ok_or(SolVeilErrors::MathOverflow)?;
+        self.expires_at = self.expires_at.max(tranche.end_time());
+        self.tranches.push(tranche);
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,254 @@
+use anchor_lang::prelude::*;
+
+use crate::errors::SolVeilErrors;
+
+pub const SECONDS_PER_MONTH: u64 = 30 * 86_400;
+
+/// How a tranche's non-upfront share vests over its duration. Whatever the schedule, a tranche
+/// is fully vested once its duration has elapsed.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VestingSchedule {
+    /// Continuous vesting from start to end
+    Linear,
+    /// Nothing vests until `cliff` seconds into each tranche, then vesting catches up to the
+    /// linear curve. The plan's `cliff_duration` is the once-per-subscription counterpart
+    CliffLinear { cliff: u64 },
+    /// Vests in whole `step`-second installments, e.g. `SECONDS_PER_MONTH`
+    Stepped { step: u64 },
+}
+
+impl VestingSchedule {
+    pub const LEN: usize = 1 + 8;
+
+    pub fn validate(&self) -> Result<()> {
+        if let Self::Stepped { step } = self {
+            require!(*step > 0, SolVeilErrors::InvalidVestingSchedule);
+        }
+        Ok(())
+    }
+
+    /// Part of `amount` vested `elapsed` seconds into a `duration`-long tranche, with `upfront`
+    /// vested from the start.
+    pub fn vested(&self, amount: u64, upfront: u64, duration: u64, elapsed: u64) -> u64 {
+        let upfront = upfront.min(amount);
+        let remaining = amount - upfront;
+        if duration == 0 || elapsed >= duration {
+            return amount;
+        }
+
+        let counted = match *self {
+            Self::Linear => elapsed,
+            Self::CliffLinear { cliff } if elapsed < cliff => 0,
+            Self::CliffLinear { .. } => elapsed,
+            Self::Stepped { step } => elapsed - elapsed % step.max(1),
+        };
+        let vested_linear = ((remaining as u128 * counted as u128) / duration as u128) as u64;
+        upfront + vested_linear
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct VestingTranche {
+    pub start_time: u64,
+    pub duration: u64,
+    pub amount: u64,
+    pub upfront_amount: u64,
+}
+
+impl VestingTranche {
+    pub const LEN: usize = 8 + 8 + 8 + 8;
+
+    pub fn end_time(&self) -> u64 {
+        self.start_time.saturating_add(self.duration)
+    }
+
+    /// Vested amount under `schedule`: nothing before the tranche starts, and only the upfront
+    /// share until `cliff` seconds in (or the tranche ends, if sooner).
+    pub fn vested_at(&self, schedule: &VestingSchedule, cliff: u64, now: u64) -> u64 {
+        if now < self.start_time {
+            return 0;
+        }
+        let elapsed = now - self.start_time;
+        if elapsed < cliff && elapsed < self.duration {
+            return self.upfront_amount.min(self.amount);
+        }
+        schedule.vested(self.amount, self.upfront_amount, self.duration, elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SubscriptionPlan, UserSubscription};
+    use proptest::prelude::*;
+
+    const START: u64 = 1_700_000_000;
+
+    fn plan(vesting_schedule: VestingSchedule, cliff_duration: u64) -> SubscriptionPlan {
+        SubscriptionPlan {
+            creator: Pubkey::default(),
+            payment_mint: None,
+            nft_collection: Pubkey::default(),
+            transferable: false,
+            grace_period: 0,
+            creation_timestamp: START,
+            bump: 0,
+            vault_bump: 0,
+            seed: Vec::new(),
+            tiers: Vec::new(),
+            active_subscribers: 1,
+            total_deposited: 0,
+            total_claimed_by_creator: 0,
+            total_refunded: 0,
+            paused: false,
+            sunset_at: 0,
+            vesting_schedule,
+            cliff_duration,
+            trial_duration: 0,
+        }
+    }
+
+    fn subscription(cliff_duration: u64) -> UserSubscription {
+        UserSubscription {
+            plan: Pubkey::default(),
+            subscription_mint: Pubkey::default(),
+            tier: 0,
+            start_time: START,
+            expires_at: 0,
+            total_deposit_amount: 0,
+            claimed_by_creator_amount: 0,
+            refund_token_amount: 0,
+            is_active: true,
+            bump: 0,
+            settled_amount: 0,
+            tranches: Vec::new(),
+            auto_renew_enabled: false,
+            auto_renew_token: Pubkey::default(),
+            auto_renew_cap: 0,
+            auto_renew_spent: 0,
+            trial: false,
+            index: 0,
+            closed_at: 0,
+            refund_destination: None,
+            seats: 0,
+            assigned_seats: 0,
+            cliff_end: START + cliff_duration,
+        }
+    }
+
+    fn schedules() -> impl Strategy<Value = VestingSchedule> {
+        prop_oneof![
+            Just(VestingSchedule::Linear),
+            (0..2_000_000u64).prop_map(|cliff| VestingSchedule::CliffLinear { cliff }),
+            (1..2_000_000u64).prop_map(|step| VestingSchedule::Stepped { step }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn vested_and_refundable_sum_to_deposit(
+            schedule in schedules(),
+            cliff in 0..2_000_000u64,
+            renewals in prop::collection::vec(
+                (0..u32::MAX as u64, 0..=100u64, 1..1_000_000u64, 0..1_500_000u64),
+                1..20,
+            ),
+            checked_at in prop::collection::vec(0..40_000_000u64, 1..8),
+        ) {
+            let cliff = if schedule == VestingSchedule::Linear { cliff } else { 0 };
+            let plan = plan(schedule, cliff);
+            let mut sub = subscription(cliff);
+            let mut now = START;
+            for (amount, upfront_pct, duration, renew_after) in renewals {
+                let tranche = VestingTranche {
+                    start_time: sub.expires_at.max(now),
+                    duration,
+                    amount,
+                    upfront_amount: amount * upfront_pct / 100,
+                };
+                prop_assume!(sub.add_tranche(tranche, now).is_ok());
+                now += renew_after;
+            }
+
+            // Settlement refunds `total_deposit_amount - vested`, so vested must never exceed it,
+            // whatever has been folded into `settled_amount`
+            let mut last = 0;
+            let mut checked_at = checked_at;
+            checked_at.sort_unstable();
+            for at in checked_at {
+                let vested = sub.vested_amount(&plan, START + at);
+                let refund = sub.total_deposit_amount.checked_sub(vested);
+                prop_assert_eq!(refund.map(|r| r + vested), Some(sub.total_deposit_amount));
+                prop_assert!(vested >= sub.settled_amount && vested >= last);
+                last = vested;
+            }
+            prop_assert_eq!(sub.vested_amount(&plan, sub.expires_at), sub.total_deposit_amount);
+        }
+
+        #[test]
+        fn vesting_never_decreases(
+            schedule in schedules(),
+            amount in any::<u64>(),
+            upfront in any::<u64>(),
+            duration in 0..10_000_000u64,
+            earlier in 0..20_000_000u64,
+            later_by in 0..20_000_000u64,
+        ) {
+            let before = schedule.vested(amount, upfront, duration, earlier);
+            let after = schedule.vested(amount, upfront, duration, earlier + later_by);
+            prop_assert!(before <= after);
+        }
+
+        #[test]
+        fn fully_vested_at_end(
+            schedule in schedules(),
+            amount in any::<u64>(),
+            upfront in any::<u64>(),
+            duration in 0..10_000_000u64,
+            past_end in 0..1_000_000u64,
+        ) {
+            prop_assert_eq!(schedule.vested(amount, upfront, duration, duration + past_end), amount);
+        }
+
+        #[test]
+        fn cliff_keeps_tranche_monotonic_and_bounded(
+            schedule in schedules(),
+            amount in any::<u64>(),
+            upfront in any::<u64>(),
+            duration in 0..10_000_000u64,
+            cliff in 0..20_000_000u64,
+            earlier in 0..20_000_000u64,
+            later_by in 0..20_000_000u64,
+        ) {
+            let tranche = VestingTranche { start_time: 1_000, duration, amount, upfront_amount: upfront };
+            let before = tranche.vested_at(&schedule, cliff, 1_000 + earlier);
+            let after = tranche.vested_at(&schedule, cliff, 1_000 + earlier + later_by);
+            prop_assert!(before <= after);
+            prop_assert!(after <= amount);
+            prop_assert_eq!(tranche.vested_at(&schedule, cliff, tranche.end_time()), amount);
+        }
+    }
+
+    #[test]
+    fn cliff_holds_back_everything_but_upfront() {
+        let schedule = VestingSchedule::CliffLinear { cliff: 100 };
+        assert_eq!(schedule.vested(1_000, 100, 1_000, 99), 100);
+        assert_eq!(schedule.vested(1_000, 100, 1_000, 100), 190);
+    }
+
+    #[test]
+    fn plan_cliff_only_releases_upfront() {
+        let tranche = VestingTranche { start_time: 0, duration: 1_000, amount: 1_000, upfront_amount: 100 };
+        assert_eq!(tranche.vested_at(&VestingSchedule::Linear, 500, 499), 100);
+        assert_eq!(tranche.vested_at(&VestingSchedule::Linear, 500, 500), 550);
+    }
+
+    #[test]
+    fn stepped_releases_whole_installments() {
+        let schedule = VestingSchedule::Stepped { step: SECONDS_PER_MONTH };
+        let duration = 3 * SECONDS_PER_MONTH;
+        assert_eq!(schedule.vested(900, 0, duration, SECONDS_PER_MONTH - 1), 0);
+        assert_eq!(schedule.vested(900, 0, duration, SECONDS_PER_MONTH), 300);
+        assert_eq!(schedule.vested(900, 0, duration, 2 * SECONDS_PER_MONTH + 5), 600);
+    }
+}
@@ -3,18 +3,30 @@ import { Program, BN } from "@coral-xyz/anchor";
 import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
 import {
   TOKEN_PROGRAM_ID,
+  TOKEN_2022_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID,
+  ExtensionType,
+  getMintLen,
+  createInitializeTransferFeeConfigInstruction,
   createInitializeMintInstruction,
+  createInitializeAccountInstruction,
+  ACCOUNT_SIZE,
   getAssociatedTokenAddressSync,
   createAssociatedTokenAccountInstruction,
   createMintToInstruction,
   createTransferInstruction,
+  createBurnInstruction,
 } from "@solana/spl-token";
 import { assert } from "chai";
 import { Solvency } from "../target/types/solvency";
 
 const METADATA_PROGRAM_ID = new PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+const BPF_LOADER_UPGRADEABLE_ID = new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111");
 const programId = new PublicKey("FisvpEC1NDf4kZtzJY3cBvA6xJnohVxjD3WvzxJk5jRu");
+const [programDataPda] = PublicKey.findProgramAddressSync(
+  [programId.toBuffer()],
+  BPF_LOADER_UPGRADEABLE_ID
+);
 
 const provider = anchor.AnchorProvider.local("http://127.0.0.1:8899");
 anchor.setProvider(provider);
@@ -35,9 +47,63 @@ function findVaultPda(planPda: PublicKey): [PublicKey, number] {
   );
 }
 
-function findUserSubscriptionPda(planPda: PublicKey, user: PublicKey): [PublicKey, number] {
+function subscriptionIndexSeed(index: number): Buffer {
+  const seed = Buffer.alloc(4);
+  seed.writeUInt32LE(index);
+  return seed;
+}
+
+function findUserSubscriptionPda(planPda: PublicKey, user: PublicKey, index = 0): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("user_subscription"), planPda.toBuffer(), user.toBuffer(), subscriptionIndexSeed(index)],
+    programId
+  );
+}
+
+function findSubscriberIndexPda(planPda: PublicKey, user: PublicKey): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("subscriber_index"), planPda.toBuffer(), user.toBuffer()],
+    programId
+  );
+}
+
+function findCollectionMintPda(planPda: PublicKey): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("collection"), planPda.toBuffer()],
+    programId
+  );
+}
+
+function collectionAccounts(planPda: PublicKey) {
+  const [collectionMint] = findCollectionMintPda(planPda);
+  return {
+    collectionMint,
+    collectionMetadata: findMetadataPda(collectionMint)[0],
+    collectionMasterEdition: findMasterEditionPda(collectionMint)[0],
+  };
+}
+
+// Walks the Borsh layout of a Token Metadata account up to its `collection` field
+function readMetadataCollection(data: Buffer): { verified: boolean; key: PublicKey } | null {
+  let offset = 1 + 32 + 32; // key, update authority, mint
+  for (let i = 0; i < 3; i++) {
+    offset += 4 + data.readUInt32LE(offset); // name, symbol, uri
+  }
+  offset += 2; // seller fee basis points
+  if (data[offset++] === 1) {
+    offset += 4 + data.readUInt32LE(offset) * 34; // creators
+  }
+  offset += 2; // primary sale happened, is mutable
+  for (let i = 0; i < 2; i++) {
+    if (data[offset++] === 1) offset += 1; // edition nonce, token standard
+  }
+  if (data[offset++] === 0) return null;
+  return { verified: data[offset] === 1, key: new PublicKey(data.subarray(offset + 1, offset + 33)) };
+}
+
+function findMintSubscriptionPda(planPda: PublicKey, nftMint: PublicKey, index = 0): [PublicKey, number] {
   return PublicKey.findProgramAddressSync(
-    [Buffer.from("user_subscription"), planPda.toBuffer(), user.toBuffer()],
+    [Buffer.from("user_subscription"), planPda.toBuffer(), nftMint.toBuffer(), subscriptionIndexSeed(index)],
     programId
   );
 }
@@ -82,15 +148,46 @@ describe("solvency", () => {
     return mintKp.publicKey;
   }
 
-  async function createTokenAccount(mint: PublicKey, owner: PublicKey): Promise<PublicKey> {
-    const ata = getAssociatedTokenAddressSync(mint, owner);
+  // Token-2022 mint charging `feeBasisPoints` on every transfer
+  async function createTransferFeeMint(decimals: number, feeBasisPoints: number): Promise<PublicKey> {
+    const mintKp = Keypair.generate();
+    const space = getMintLen([ExtensionType.TransferFeeConfig]);
+    const lamports = await provider.connection.getMinimumBalanceForRentExemption(space);
+    const tx = new anchor.web3.Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: payer.publicKey,
+        newAccountPubkey: mintKp.publicKey,
+        lamports,
+        space,
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      createInitializeTransferFeeConfigInstruction(
+        mintKp.publicKey,
+        payer.publicKey,
+        payer.publicKey,
+        feeBasisPoints,
+        BigInt("18446744073709551615"),
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createInitializeMintInstruction(mintKp.publicKey, decimals, payer.publicKey, null, TOKEN_2022_PROGRAM_ID)
+    );
+    await provider.sendAndConfirm(tx, [mintKp]);
+    return mintKp.publicKey;
+  }
+
+  async function createTokenAccount(
+    mint: PublicKey,
+    owner: PublicKey,
+    tokenProgram: PublicKey = TOKEN_PROGRAM_ID
+  ): Promise<PublicKey> {
+    const ata = getAssociatedTokenAddressSync(mint, owner, false, tokenProgram);
     const tx = new anchor.web3.Transaction().add(
       createAssociatedTokenAccountInstruction(
         payer.publicKey,
         ata,
         owner,
         mint,
-        TOKEN_PROGRAM_ID,
+        tokenProgram,
         ASSOCIATED_TOKEN_PROGRAM_ID
       )
     );
@@ -98,60 +195,118 @@ describe("solvency", () => {
     return ata;
   }
 
-  async function mintTokens(mint: PublicKey, destination: PublicKey, amount: number | bigint) {
+  async function mintTokens(
+    mint: PublicKey,
+    destination: PublicKey,
+    amount: number | bigint,
+    tokenProgram: PublicKey = TOKEN_PROGRAM_ID
+  ) {
     const tx = new anchor.web3.Transaction().add(
-      createMintToInstruction(mint, destination, payer.publicKey, BigInt(amount), [], TOKEN_PROGRAM_ID)
+      createMintToInstruction(mint, destination, payer.publicKey, BigInt(amount), [], tokenProgram)
     );
     await provider.sendAndConfirm(tx, []);
   }
 
-  async function createPlan(
-    paymentMint: PublicKey,
-    nftCollection: PublicKey,
-    upfrontPercentage: number,
-    vestingDuration: number,
-    planSeed: string
+  type Tier = { price: number; vestingDuration: number; upfrontPercentage: number; uri: string };
+
+  // Pass a null payment mint for a plan priced in SOL
+  async function createTieredPlan(
+    paymentMint: PublicKey | null,
+    tiers: Tier[],
+    planSeed: string,
+    transferable: boolean = false,
+    gracePeriod: number = 0,
+    paymentTokenProgram: PublicKey = TOKEN_PROGRAM_ID,
+    vestingSchedule: any = { linear: {} },
+    cliffDuration: number = 0,
+    trialDuration: number = 0
   ): Promise<{ planPda: PublicKey; vaultPda: PublicKey }> {
     const creator = payer.publicKey;
     const [planPda] = findPlanPda(creator, planSeed);
     const [vaultPda] = findVaultPda(planPda);
+    const [collectionMint] = findCollectionMintPda(planPda);
+
+    const tierArgs = tiers.map((t) => ({
+      price: new BN(t.price),
+      vestingDuration: new BN(t.vestingDuration),
+      upfrontPercentage: t.upfrontPercentage,
+      uri: t.uri,
+    }));
 
     await program.methods
-      .createPlan(planSeed, upfrontPercentage, new BN(vestingDuration), nftCollection)
+      .createPlan(planSeed, {
+        tiers: tierArgs,
+        vestingSchedule,
+        cliffDuration: new BN(cliffDuration),
+        trialDuration: new BN(trialDuration),
+        transferable,
+        gracePeriod: new BN(gracePeriod),
+        collectionName: "Plan Collection",
+        collectionSymbol: "COL",
+        collectionUri: "collection_uri",
+      })
       .accounts({
         plan: planPda,
         creator,
         paymentMint,
-        vault: vaultPda,
+        vault: paymentMint ? vaultPda : null,
+        solVault: paymentMint ? null : vaultPda,
+        collectionMint,
+        collectionToken: getAssociatedTokenAddressSync(collectionMint, planPda, true),
+        collectionMetadata: findMetadataPda(collectionMint)[0],
+        collectionMasterEdition: findMasterEditionPda(collectionMint)[0],
         tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: paymentMint ? paymentTokenProgram : null,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
         rent: anchor.web3.SYSVAR_RENT_PUBKEY,
       })
+      .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
       .rpc();
 
     return { planPda, vaultPda };
   }
 
+  async function createPlan(
+    paymentMint: PublicKey,
+    upfrontPercentage: number,
+    vestingDuration: number,
+    planSeed: string,
+    price: number = 1000 * 10**6
+  ): Promise<{ planPda: PublicKey; vaultPda: PublicKey }> {
+    return createTieredPlan(
+      paymentMint,
+      [{ price, vestingDuration, upfrontPercentage, uri: "uri" }],
+      planSeed
+    );
+  }
+
   async function buySubscription(
     planPda: PublicKey,
     vaultPda: PublicKey,
     user: Keypair,
     userTokenAccount: PublicKey,
     nftMint: Keypair,
-    amount: number | string | BN,
+    tierIndex: number,
     name: string,
     symbol: string,
-    uri: string,
     paymentMint: PublicKey,
-    creatorToken: PublicKey
+    creatorToken: PublicKey,
+    transferable: boolean = false,
+    paymentTokenProgram: PublicKey = TOKEN_PROGRAM_ID,
+    treasuryToken: PublicKey | null = null,
+    subscriptionIndex: number = 0
   ) {
-    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const userSubPda = transferable
+      ? findMintSubscriptionPda(planPda, nftMint.publicKey, subscriptionIndex)[0]
+      : findUserSubscriptionPda(planPda, user.publicKey, subscriptionIndex)[0];
     const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
     const [metadataPda] = findMetadataPda(nftMint.publicKey);
     const [masterEditionPda] = findMasterEditionPda(nftMint.publicKey);
 
     await program.methods
-      .buySubscription(new BN(amount), name, symbol, uri)
+      .buySubscription(tierIndex, name, symbol)
       .accounts({
         user: user.publicKey,
         plan: planPda,
@@ -164,41 +319,110 @@ describe("solvency", () => {
         masterEdition: masterEditionPda,
         paymentMint,
         tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         tokenMetadataProgram: METADATA_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
         rent: anchor.web3.SYSVAR_RENT_PUBKEY,
-        creatorToken
+        creatorToken,
+        protocolFee: { treasuryToken },
+        ...collectionAccounts(planPda),
       })
       .signers([user, nftMint])
+      .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+      .rpc();
+  }
+
+  // Gifts a non-transferable subscription; the recipient co-signs so the NFT can be frozen
+  async function giftSubscription(
+    planPda: PublicKey,
+    vaultPda: PublicKey,
+    gifter: Keypair,
+    gifterToken: PublicKey,
+    recipient: Keypair,
+    nftMint: Keypair,
+    tierIndex: number,
+    paymentMint: PublicKey,
+    creatorToken: PublicKey,
+    refundToPayer: boolean = true
+  ) {
+    await program.methods
+      .giftSubscription(tierIndex, "Gift", "GFT", refundToPayer)
+      .accounts({
+        payer: gifter.publicKey,
+        recipient: recipient.publicKey,
+        plan: planPda,
+        vault: vaultPda,
+        payerToken: gifterToken,
+        userSubscription: findUserSubscriptionPda(planPda, recipient.publicKey)[0],
+        nftMint: nftMint.publicKey,
+        nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, recipient.publicKey),
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        creatorToken,
+        ...collectionAccounts(planPda),
+      })
+      .signers([gifter, recipient, nftMint])
+      .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+      .rpc();
+  }
+
+  // Anchor events logged by a confirmed transaction
+  async function eventsOf(signature: string) {
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    return Array.from(parser.parseLogs(tx!.meta!.logMessages!));
+  }
+
+  async function setProtocolFee(feeBps: number, treasury: PublicKey) {
+    await program.methods
+      .updateProtocolConfig(feeBps, treasury)
+      .accounts({
+        admin: payer.publicKey,
+        programData: programDataPda,
+      })
       .rpc();
   }
 
+  // Start every test from a zero protocol fee
+  before(async () => {
+    await setProtocolFee(0, payer.publicKey);
+  });
+
   it("create plan success", async () => {
     const paymentMint = await createMint(6);
-    const nftCollection = Keypair.generate().publicKey;
     const upfrontPercentage = 20;
     const vestingDuration = 86400;
     const planSeed = "test_plan";
 
-    const { planPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
 
     const plan = await program.account.subscriptionPlan.fetch(planPda);
     assert(plan.creator.equals(payer.publicKey));
-    assert(plan.upfrontPercentage === upfrontPercentage);
-    assert(plan.vestingDuration.eq(new BN(vestingDuration)));
+    assert(plan.tiers.length === 1);
+    assert(plan.tiers[0].upfrontPercentage === upfrontPercentage);
+    assert(plan.tiers[0].vestingDuration.eq(new BN(vestingDuration)));
     assert(plan.paymentMint.equals(paymentMint));
-    assert(plan.nftCollection.equals(nftCollection));
+    assert(plan.nftCollection.equals(findCollectionMintPda(planPda)[0]));
   });
 
 it("buy subscription success", async () => {
   const paymentMint = await createMint(6);
-  const nftCollection = Keypair.generate().publicKey;
   const upfrontPercentage = 20;
   const vestingDuration = 86400;
   const planSeed = "buy_test_" + Date.now();
 
-  const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+  const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
 
   const user = Keypair.generate();
   await provider.connection.confirmTransaction(
@@ -213,7 +437,7 @@ it("buy subscription success", async () => {
   
   // ✅ Добавляем инструкцию для увеличения compute units
   const tx = await program.methods
-    .buySubscription(new BN(1000 * 10**6), "NFT Name", "SYM", "uri")
+    .buySubscription(0, "NFT Name", "SYM")
     .accounts({
       user: user.publicKey,
       plan: planPda,
@@ -226,11 +450,13 @@ it("buy subscription success", async () => {
       masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
       paymentMint,
       tokenProgram: TOKEN_PROGRAM_ID,
+      paymentTokenProgram: TOKEN_PROGRAM_ID,
       associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       tokenMetadataProgram: METADATA_PROGRAM_ID,
       systemProgram: SystemProgram.programId,
       rent: anchor.web3.SYSVAR_RENT_PUBKEY,
-      creatorToken
+      creatorToken,
+      ...collectionAccounts(planPda),
     })
     .signers([user, nftMint])
     .transaction();
@@ -252,12 +478,11 @@ it("buy subscription success", async () => {
   await anchor.web3.sendAndConfirmTransaction(provider.connection, tx, [user, nftMint, payer.payer]);
 });it("buy subscription success", async () => {
   const paymentMint = await createMint(6);
-  const nftCollection = Keypair.generate().publicKey;
   const upfrontPercentage = 20;
   const vestingDuration = 86400;
   const planSeed = "buy_test_" + Date.now();
 
-  const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+  const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
 
   const user = Keypair.generate();
   await provider.connection.confirmTransaction(
@@ -272,7 +497,7 @@ it("buy subscription success", async () => {
   
   // ✅ Добавляем инструкцию для увеличения compute units
   const tx = await program.methods
-    .buySubscription(new BN(1000 * 10**6), "NFT Name", "SYM", "uri")
+    .buySubscription(0, "NFT Name", "SYM")
     .accounts({
       user: user.publicKey,
       plan: planPda,
@@ -285,11 +510,13 @@ it("buy subscription success", async () => {
       masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
       paymentMint,
       tokenProgram: TOKEN_PROGRAM_ID,
+      paymentTokenProgram: TOKEN_PROGRAM_ID,
       associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       tokenMetadataProgram: METADATA_PROGRAM_ID,
       systemProgram: SystemProgram.programId,
       rent: anchor.web3.SYSVAR_RENT_PUBKEY,
-      creatorToken
+      creatorToken,
+      ...collectionAccounts(planPda),
     })
     .signers([user, nftMint])
     .transaction();
@@ -313,12 +540,11 @@ it("buy subscription success", async () => {
 
   it("renew subscription success", async () => {
     const paymentMint = await createMint(6);
-    const nftCollection = Keypair.generate().publicKey;
     const upfrontPercentage = 20;
     const vestingDuration = 86400;
     const planSeed = "renew_test";
 
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
 
     const user = Keypair.generate();
     await provider.connection.confirmTransaction(
@@ -330,7 +556,7 @@ it("buy subscription success", async () => {
 
     const nftMint = Keypair.generate();
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 1000 * 10**6, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
 
     const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
 
@@ -342,6 +568,7 @@ it("buy subscription success", async () => {
         vault: vaultPda,
         userToken,
         userSubscription: userSubPda,
+        nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
         paymentMint,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
@@ -355,12 +582,11 @@ it("buy subscription success", async () => {
 it("claim tokens success", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    const nftCollection = Keypair.generate().publicKey;
     const upfrontPercentage = 0;
     const vestingDuration = 10; // Увеличим до 10 секунд для точности
     const planSeed = "claim_test_" + Math.random();
 
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
 
     const user = Keypair.generate();
     await provider.connection.confirmTransaction(
@@ -372,7 +598,7 @@ it("claim tokens success", async () => {
     await mintTokens(paymentMint, userToken, amount);
 
     const nftMint = Keypair.generate();
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, amount, "NFT", "SYM", "uri", paymentMint, creatorToken);
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT", "SYM", paymentMint, creatorToken);
 
     // Спим 2 секунды (это 20% от 10 секунд вестинга)
     await sleep(2000);
@@ -406,14 +632,12 @@ it("claim tokens success", async () => {
 it("close subscription success", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    // 1. Объявляем nftCollection, которой не хватало
-    const nftCollection = Keypair.generate().publicKey; 
     const upfrontPercentage = 0;
     // 2. Ставим вестинг 1000 секунд, чтобы за время теста ничего не успело "сгореть"
     const vestingDuration = 1000; 
     const planSeed = "close_test_" + Math.random(); // Уникальный сид
 
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
 
     const user = Keypair.generate();
     await provider.connection.confirmTransaction(
@@ -427,24 +651,29 @@ it("close subscription success", async () => {
     const nftMint = Keypair.generate();
     
     // Покупаем подписку
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, depositAmount, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
 
     const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
     const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
 
     // Закрываем сразу же, без sleep, чтобы гарантировать возврат
     await program.methods
-      .closeSubscription()
+      .closeSubscription(false)
       .accounts({
         user: user.publicKey,
         plan: planPda,
         userSubscription: userSubPda,
+        paymentMint,
         vault: vaultPda,
         userToken,
         creatorToken,
         nftMint: nftMint.publicKey,
         nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
         tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
       .signers([user])
@@ -473,317 +702,2340 @@ it("close subscription success", async () => {
     assert.isAbove(Number(userBalance.value.amount), depositAmount * 0.95, "Юзер должен получить большую часть рефанда");
   });
 
-it("query unvested balance", async () => {
-  const paymentMint = await createMint(6);
-  const nftCollection = Keypair.generate().publicKey;
-  const upfrontPercentage = 20;
-  const vestingDuration = 2; // 2 секунды
-  const planSeed = "query_test_" + Date.now();
-
-  const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
-
-  const user = Keypair.generate();
-  await provider.connection.confirmTransaction(
-    await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
-  );
-
-  const userToken = await createTokenAccount(paymentMint, user.publicKey);
-  await mintTokens(paymentMint, userToken, 1000 * 10**6);
-
-  const nftMint = Keypair.generate();
-  const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-  
-  // Добавляем compute units и для этой транзакции
-  const buyTx = await program.methods
-    .buySubscription(new BN(1000 * 10**6), "NFT Name", "SYM", "uri")
-    .accounts({
-      user: user.publicKey,
-      plan: planPda,
-      vault: vaultPda,
-      userToken: userToken,
-      userSubscription: findUserSubscriptionPda(planPda, user.publicKey)[0],
-      nftMint: nftMint.publicKey,
-      nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
-      metadata: findMetadataPda(nftMint.publicKey)[0],
-      masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
-      paymentMint,
-      tokenProgram: TOKEN_PROGRAM_ID,
-      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
-      tokenMetadataProgram: METADATA_PROGRAM_ID,
-      systemProgram: SystemProgram.programId,
-      rent: anchor.web3.SYSVAR_RENT_PUBKEY,
-      creatorToken
-    })
-    .signers([user, nftMint])
-    .transaction();
-
-  buyTx.add(
-    anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })
-  );
-  
-  await anchor.web3.sendAndConfirmTransaction(provider.connection, buyTx, [user, nftMint, payer.payer]);
-
-  // Ждем 1.1 секунды (половина от 2 секунд)
-  await sleep(1100);
-
-  const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
-  const userSub = await program.account.userSubscription.fetch(userSubPda);
-  const plan = await program.account.subscriptionPlan.fetch(planPda);
-
-  const currentTime = Math.floor(Date.now() / 1000);
-  const elapsed = currentTime - userSub.startTime.toNumber();
-  
-  // Правильный расчет вестинга
-  const totalAmount = userSub.totalDepositAmount.toNumber();
-  const upfront = (plan.upfrontPercentage * totalAmount) / 100;
-  const remaining = totalAmount - upfront;
-  
-  // Время не может превышать длительность вестинга
-  const vestingTime = Math.min(elapsed, plan.vestingDuration.toNumber());
-  const vestedLinear = (remaining * vestingTime) / plan.vestingDuration.toNumber();
-  const vested = upfront + vestedLinear;
-  const unvested = totalAmount - vested;
-
-  console.log("Elapsed:", elapsed, "s");
-  console.log("Unvested:", unvested / 10**6);
-  console.log("Vested:", vested / 10**6);
-  
-  // За 1 секунду должно быть ~40% от оставшихся 80% = 32% + 20% upfront = 52% vested, 48% unvested
-  assert.approximately(unvested / 10**6, 480, 50);
-});
-
-  it("prevent double spend after burn", async () => {
+  it("archives a closed subscription and lets the user subscribe again", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    const nftCollection = Keypair.generate().publicKey;
-    const upfrontPercentage = 0;
-    const vestingDuration = 2;
-    const planSeed = "double_spend";
-
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createPlan(paymentMint, 20, 1000, "archive_" + Date.now());
 
     const user = Keypair.generate();
     await provider.connection.confirmTransaction(
       await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
     );
-
     const userToken = await createTokenAccount(paymentMint, user.publicKey);
-    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+    await mintTokens(paymentMint, userToken, 2000 * 10**6);
 
     const nftMint = Keypair.generate();
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 1000 * 10**6, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
-
-    await sleep(1100);
-
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
     const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
-    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
 
     await program.methods
-      .closeSubscription()
+      .closeSubscription(true)
       .accounts({
         user: user.publicKey,
         plan: planPda,
         userSubscription: userSubPda,
+        paymentMint,
         vault: vaultPda,
         userToken,
         creatorToken,
         nftMint: nftMint.publicKey,
-        nftAta,
+        nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
         tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
       })
       .signers([user])
       .rpc();
 
-    try {
-      await program.methods
-        .closeSubscription()
-        .accounts({
-          user: user.publicKey,
-          plan: planPda,
-          userSubscription: userSubPda,
-          vault: vaultPda,
-          userToken,
-          creatorToken,
-          nftMint: nftMint.publicKey,
-          nftAta,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          systemProgram: SystemProgram.programId,
-        })
-        .signers([user])
-        .rpc();
-      assert.fail("Should have failed");
-    } catch (e: any) {
-      assert(e.message.includes("AccountNotInitialized") || e.message.includes("does not exist"));
-    }
+    const archived = await program.account.userSubscription.fetch(userSubPda);
+    assert.isFalse(archived.isActive);
+    assert.equal(archived.index, 0);
+    assert.isAbove(archived.closedAt.toNumber(), 0);
+    assert.equal(
+      archived.refundTokenAmount.toNumber() + archived.claimedByCreatorAmount.toNumber(),
+      archived.totalDepositAmount.toNumber()
+    );
+
+    await buySubscription(
+      planPda, vaultPda, user, userToken, Keypair.generate(), 0, "NFT Name", "SYM",
+      paymentMint, creatorToken, false, TOKEN_PROGRAM_ID, null, 1
+    );
+    const renewed = await program.account.userSubscription.fetch(findUserSubscriptionPda(planPda, user.publicKey, 1)[0]);
+    assert.isTrue(renewed.isActive);
+    assert.equal(renewed.index, 1);
   });
 
-  it("cannot close without ownership", async () => {
+  it("lets a wallet hold several subscriptions to the same plan", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    const nftCollection = Keypair.generate().publicKey;
-    const upfrontPercentage = 0;
-    const vestingDuration = 2;
-    const planSeed = "ownership";
-
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createPlan(paymentMint, 20, 1000, "concurrent_" + Date.now());
 
-    const userA = Keypair.generate();
+    const user = Keypair.generate();
     await provider.connection.confirmTransaction(
-      await provider.connection.requestAirdrop(userA.publicKey, 10 * LAMPORTS_PER_SOL)
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
     );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 3000 * 10**6);
 
-    const userAToken = await createTokenAccount(paymentMint, userA.publicKey);
-    await mintTokens(paymentMint, userAToken, 1000 * 10**6);
-
-    const nftMint = Keypair.generate();
-    await buySubscription(planPda, vaultPda, userA, userAToken, nftMint, 1000 * 10**6, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
-
-    const userSubPda = findUserSubscriptionPda(planPda, userA.publicKey)[0];
-    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, userA.publicKey);
+    for (let index = 0; index < 3; index++) {
+      await buySubscription(
+        planPda, vaultPda, user, userToken, Keypair.generate(), 0, "NFT Name", "SYM",
+        paymentMint, creatorToken, false, TOKEN_PROGRAM_ID, null, index
+      );
+    }
 
-    const userB = Keypair.generate();
-    const userBToken = await createTokenAccount(paymentMint, userB.publicKey);
+    const subscriberIndex = await program.account.subscriberIndex.fetch(
+      findSubscriberIndexPda(planPda, user.publicKey)[0]
+    );
+    assert.equal(subscriberIndex.nextIndex, 3);
+    assert.deepEqual(
+      subscriberIndex.subscriptions.map((key) => key.toBase58()),
+      [0, 1, 2].map((index) => findUserSubscriptionPda(planPda, user.publicKey, index)[0].toBase58())
+    );
 
-    try {
-      await program.methods
-        .closeSubscription()
-        .accounts({
-          user: userB.publicKey,
-          plan: planPda,
-          userSubscription: userSubPda,
-          vault: vaultPda,
-          userToken: userBToken,
-          creatorToken,
-          nftMint: nftMint.publicKey,
-          nftAta,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          systemProgram: SystemProgram.programId,
-        })
-        .signers([userB])
-        .rpc();
-      assert.fail("Should have failed");
-    } catch (e: any) {
-      assert(e.message.includes("Unauthorized") || e.message.includes("ConstraintHasOne") || e.message.includes("constraint was violated"));
-    }
+    const plan = await program.account.subscriptionPlan.fetch(planPda);
+    assert.equal(plan.activeSubscribers.toNumber(), 3);
   });
 
-  it("cannot claim if not creator", async () => {
+  it("gifts a subscription and refunds the payer on close", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    const nftCollection = Keypair.generate().publicKey;
-    const upfrontPercentage = 0;
-    const vestingDuration = 2;
-    const planSeed = "claim_auth";
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 1000, "gift_" + Date.now());
+
+    const gifter = Keypair.generate();
+    const recipient = Keypair.generate();
+    for (const wallet of [gifter, recipient]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(wallet.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
+    const gifterToken = await createTokenAccount(paymentMint, gifter.publicKey);
+    const depositAmount = 1000 * 10**6;
+    await mintTokens(paymentMint, gifterToken, depositAmount);
 
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const nftMint = Keypair.generate();
+    const userSubPda = findUserSubscriptionPda(planPda, recipient.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, recipient.publicKey);
 
-    const user = Keypair.generate();
-    await provider.connection.confirmTransaction(
-      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
-    );
+    await giftSubscription(planPda, vaultPda, gifter, gifterToken, recipient, nftMint, 0, paymentMint, creatorToken);
 
+    const gifted = await program.account.userSubscription.fetch(userSubPda);
+    assert.isTrue(gifted.isActive);
+    assert.equal(gifted.refundDestination.toBase58(), gifter.publicKey.toBase58());
+    assert.equal((await provider.connection.getTokenAccountBalance(nftAta)).value.amount, "1");
+
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: recipient.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        refundToken: gifterToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([recipient])
+      .rpc();
+
+    const gifterBalance = Number((await provider.connection.getTokenAccountBalance(gifterToken)).value.amount);
+    const creatorBalance = Number((await provider.connection.getTokenAccountBalance(creatorToken)).value.amount);
+    assert.equal(gifterBalance + creatorBalance, depositAmount);
+    assert.isAbove(gifterBalance, depositAmount * 0.95);
+  });
+
+  it("refunds a renewed gift to the recipient who renewed it", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 1000, "gift_renewal_" + Date.now());
+
+    const gifter = Keypair.generate();
+    const recipient = Keypair.generate();
+    for (const wallet of [gifter, recipient]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(wallet.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
+    const gifterToken = await createTokenAccount(paymentMint, gifter.publicKey);
+    const recipientToken = await createTokenAccount(paymentMint, recipient.publicKey);
+    await mintTokens(paymentMint, gifterToken, 1000 * 10**6);
+    await mintTokens(paymentMint, recipientToken, 500 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await giftSubscription(planPda, vaultPda, gifter, gifterToken, recipient, nftMint, 0, paymentMint, creatorToken);
+    const userSubPda = findUserSubscriptionPda(planPda, recipient.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, recipient.publicKey);
+
+    await program.methods
+      .renewSubscription(new BN(500 * 10**6))
+      .accounts({
+        user: recipient.publicKey,
+        plan: planPda,
+        vault: vaultPda,
+        userToken: recipientToken,
+        userSubscription: userSubPda,
+        nftAta,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([recipient])
+      .rpc();
+
+    // The recipient's own funds are in the deposit now, so refunds stop going to the gifter
+    const renewed = await program.account.userSubscription.fetch(userSubPda);
+    assert.isNull(renewed.refundDestination);
+
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: recipient.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        userToken: recipientToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([recipient])
+      .rpc();
+
+    const recipientBalance = Number((await provider.connection.getTokenAccountBalance(recipientToken)).value.amount);
+    const creatorBalance = Number((await provider.connection.getTokenAccountBalance(creatorToken)).value.amount);
+    assert.equal((await provider.connection.getTokenAccountBalance(gifterToken)).value.amount, "0");
+    assert.equal(recipientBalance + creatorBalance, 1500 * 10**6);
+    assert.isAbove(recipientBalance, 500 * 10**6);
+  });
+
+  it("refunds a gift's leftover credit to the payer on a tier downgrade", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const tiers: Tier[] = [
+      { price: 300 * 10**6, vestingDuration: 100000, upfrontPercentage: 0, uri: "pro_uri" },
+      { price: 100 * 10**6, vestingDuration: 100000, upfrontPercentage: 0, uri: "basic_uri" },
+    ];
+    const { planPda, vaultPda } = await createTieredPlan(paymentMint, tiers, "gift_tier_" + Date.now());
+
+    const gifter = Keypair.generate();
+    const recipient = Keypair.generate();
+    for (const wallet of [gifter, recipient]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(wallet.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
+    const gifterToken = await createTokenAccount(paymentMint, gifter.publicKey);
+    const recipientToken = await createTokenAccount(paymentMint, recipient.publicKey);
+    await mintTokens(paymentMint, gifterToken, 300 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await giftSubscription(planPda, vaultPda, gifter, gifterToken, recipient, nftMint, 0, paymentMint, creatorToken);
+
+    const changeAccounts = {
+      user: recipient.publicKey,
+      plan: planPda,
+      userSubscription: findUserSubscriptionPda(planPda, recipient.publicKey)[0],
+      nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, recipient.publicKey),
+      paymentMint,
+      vault: vaultPda,
+      userToken: recipientToken,
+      creatorToken,
+      metadata: findMetadataPda(nftMint.publicKey)[0],
+      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenMetadataProgram: METADATA_PROGRAM_ID,
+    };
+
+    // The refund account must belong to the gift's refund destination
+    try {
+      await program.methods
+        .changeTier(1)
+        .accounts({ ...changeAccounts, refundToken: recipientToken })
+        .signers([recipient])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("InvalidRefundDestination"));
+    }
+
+    await program.methods
+      .changeTier(1)
+      .accounts({ ...changeAccounts, refundToken: gifterToken })
+      .signers([recipient])
+      .rpc();
+
+    const gifterBalance = Number((await provider.connection.getTokenAccountBalance(gifterToken)).value.amount);
+    assert.approximately(gifterBalance, 200 * 10**6, 10**6);
+    assert.equal((await provider.connection.getTokenAccountBalance(recipientToken)).value.amount, "0");
+  });
+
+  it("sells seats, assigns and revokes them, and refunds the whole pool on close", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const price = 100 * 10**6;
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 1000, "seats_" + Date.now(), price);
+
+    const admin = Keypair.generate();
+    const member = Keypair.generate();
+    for (const wallet of [admin, member]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(wallet.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
+    const adminToken = await createTokenAccount(paymentMint, admin.publicKey);
+    await mintTokens(paymentMint, adminToken, 5 * price);
+
+    const poolMint = Keypair.generate();
+    const poolPda = findUserSubscriptionPda(planPda, admin.publicKey)[0];
+    const adminNftAta = getAssociatedTokenAddressSync(poolMint.publicKey, admin.publicKey);
+    await program.methods
+      .buySeats(0, 3, "Team", "TEAM")
+      .accounts({
+        user: admin.publicKey,
+        plan: planPda,
+        vault: vaultPda,
+        userToken: adminToken,
+        userSubscription: poolPda,
+        nftMint: poolMint.publicKey,
+        nftAta: adminNftAta,
+        metadata: findMetadataPda(poolMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(poolMint.publicKey)[0],
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        creatorToken,
+        ...collectionAccounts(planPda),
+      })
+      .signers([admin, poolMint])
+      .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+      .rpc();
+
+    let pool = await program.account.userSubscription.fetch(poolPda);
+    assert.equal(pool.seats, 3);
+    assert.equal(pool.totalDepositAmount.toNumber(), 3 * price);
+
+    const seatMint = Keypair.generate();
+    const [seatPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("seat"), poolPda.toBuffer(), member.publicKey.toBuffer()],
+      programId
+    );
+    const seatNftAta = getAssociatedTokenAddressSync(seatMint.publicKey, member.publicKey);
+    await program.methods
+      .assignSeat("Seat", "SEAT")
+      .accounts({
+        admin: admin.publicKey,
+        member: member.publicKey,
+        plan: planPda,
+        userSubscription: poolPda,
+        adminNftAta,
+        seat: seatPda,
+        nftMint: seatMint.publicKey,
+        nftAta: seatNftAta,
+        metadata: findMetadataPda(seatMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(seatMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        ...collectionAccounts(planPda),
+      })
+      .signers([admin, member, seatMint])
+      .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+      .rpc();
+
+    pool = await program.account.userSubscription.fetch(poolPda);
+    assert.equal(pool.assignedSeats, 1);
+
+    const status = await program.methods
+      .assertActiveSeat(null)
+      .accounts({
+        plan: planPda,
+        userSubscription: poolPda,
+        seat: seatPda,
+        holder: member.publicKey,
+        nftAta: seatNftAta,
+      })
+      .view();
+    assert(status.subscriptionMint.equals(seatMint.publicKey));
+
+    // The admin NFT is only the pool's record; access comes from seats
+    try {
+      await program.methods
+        .assertActiveSubscription(null)
+        .accounts({ holder: admin.publicKey, plan: planPda, userSubscription: poolPda, nftAta: adminNftAta })
+        .view();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("SeatPoolNotASubscription"));
+    }
+
+    const closePool = () =>
+      program.methods
+        .closeSubscription(false)
+        .accounts({
+          user: admin.publicKey,
+          plan: planPda,
+          userSubscription: poolPda,
+          paymentMint,
+          vault: vaultPda,
+          userToken: adminToken,
+          creatorToken,
+          nftMint: poolMint.publicKey,
+          nftAta: adminNftAta,
+          metadata: findMetadataPda(poolMint.publicKey)[0],
+          masterEdition: findMasterEditionPda(poolMint.publicKey)[0],
+          tokenProgram: TOKEN_PROGRAM_ID,
+          paymentTokenProgram: TOKEN_PROGRAM_ID,
+          tokenMetadataProgram: METADATA_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+    try {
+      await closePool();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("SeatsStillAssigned"));
+    }
+
+    await program.methods
+      .revokeSeat()
+      .accounts({
+        admin: admin.publicKey,
+        plan: planPda,
+        userSubscription: poolPda,
+        adminNftAta,
+        seat: seatPda,
+        nftMint: seatMint.publicKey,
+        seatNftAta,
+        metadata: findMetadataPda(seatMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(seatMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+      })
+      .signers([admin])
+      .rpc();
+
+    assert.isNull(await provider.connection.getAccountInfo(seatPda));
+    assert.equal((await provider.connection.getTokenAccountBalance(seatNftAta)).value.amount, "0");
+
+    await closePool();
+    const adminBalance = Number((await provider.connection.getTokenAccountBalance(adminToken)).value.amount);
+    const creatorBalance = Number((await provider.connection.getTokenAccountBalance(creatorToken)).value.amount);
+    assert.equal(adminBalance + creatorBalance, 5 * price);
+    assert.isAbove(adminBalance, 2 * price + 3 * price * 0.95);
+  });
+
+it("query unvested balance", async () => {
+  const paymentMint = await createMint(6);
+  const upfrontPercentage = 20;
+  const vestingDuration = 2; // 2 секунды
+  const planSeed = "query_test_" + Date.now();
+
+  const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+  const user = Keypair.generate();
+  await provider.connection.confirmTransaction(
+    await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+  );
+
+  const userToken = await createTokenAccount(paymentMint, user.publicKey);
+  await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+  const nftMint = Keypair.generate();
+  const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+  
+  // Добавляем compute units и для этой транзакции
+  const buyTx = await program.methods
+    .buySubscription(0, "NFT Name", "SYM")
+    .accounts({
+      user: user.publicKey,
+      plan: planPda,
+      vault: vaultPda,
+      userToken: userToken,
+      userSubscription: findUserSubscriptionPda(planPda, user.publicKey)[0],
+      nftMint: nftMint.publicKey,
+      nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+      metadata: findMetadataPda(nftMint.publicKey)[0],
+      masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+      paymentMint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      paymentTokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      tokenMetadataProgram: METADATA_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      creatorToken,
+      ...collectionAccounts(planPda),
+    })
+    .signers([user, nftMint])
+    .transaction();
+
+  buyTx.add(
+    anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })
+  );
+  
+  await anchor.web3.sendAndConfirmTransaction(provider.connection, buyTx, [user, nftMint, payer.payer]);
+
+  // Ждем 1.1 секунды (половина от 2 секунд)
+  await sleep(1100);
+
+  const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+  const userSub = await program.account.userSubscription.fetch(userSubPda);
+  const plan = await program.account.subscriptionPlan.fetch(planPda);
+
+  const currentTime = Math.floor(Date.now() / 1000);
+  const elapsed = currentTime - userSub.startTime.toNumber();
+  
+  // Правильный расчет вестинга
+  const totalAmount = userSub.totalDepositAmount.toNumber();
+  const tier = plan.tiers[userSub.tier];
+  const upfront = (tier.upfrontPercentage * totalAmount) / 100;
+  const remaining = totalAmount - upfront;
+  
+  // Время не может превышать длительность вестинга
+  const vestingTime = Math.min(elapsed, tier.vestingDuration.toNumber());
+  const vestedLinear = (remaining * vestingTime) / tier.vestingDuration.toNumber();
+  const vested = upfront + vestedLinear;
+  const unvested = totalAmount - vested;
+
+  console.log("Elapsed:", elapsed, "s");
+  console.log("Unvested:", unvested / 10**6);
+  console.log("Vested:", vested / 10**6);
+  
+  // За 1 секунду должно быть ~40% от оставшихся 80% = 32% + 20% upfront = 52% vested, 48% unvested
+  assert.approximately(unvested / 10**6, 480, 50);
+});
+
+  it("prevent double spend after burn", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const upfrontPercentage = 0;
+    const vestingDuration = 2;
+    const planSeed = "double_spend";
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    await sleep(1100);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    try {
+      await program.methods
+        .closeSubscription(false)
+        .accounts({
+          user: user.publicKey,
+          plan: planPda,
+          userSubscription: userSubPda,
+          paymentMint,
+          vault: vaultPda,
+          userToken,
+          creatorToken,
+          nftMint: nftMint.publicKey,
+          nftAta,
+          metadata: findMetadataPda(nftMint.publicKey)[0],
+          masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+          tokenProgram: TOKEN_PROGRAM_ID,
+          paymentTokenProgram: TOKEN_PROGRAM_ID,
+          tokenMetadataProgram: METADATA_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("AccountNotInitialized") || e.message.includes("does not exist"));
+    }
+  });
+
+  it("cannot close without ownership", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const upfrontPercentage = 0;
+    const vestingDuration = 2;
+    const planSeed = "ownership";
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+    const userA = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(userA.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userAToken = await createTokenAccount(paymentMint, userA.publicKey);
+    await mintTokens(paymentMint, userAToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, userA, userAToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, userA.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, userA.publicKey);
+
+    const userB = Keypair.generate();
+    const userBToken = await createTokenAccount(paymentMint, userB.publicKey);
+
+    try {
+      await program.methods
+        .closeSubscription(false)
+        .accounts({
+          user: userB.publicKey,
+          plan: planPda,
+          userSubscription: userSubPda,
+          paymentMint,
+          vault: vaultPda,
+          userToken: userBToken,
+          creatorToken,
+          nftMint: nftMint.publicKey,
+          nftAta,
+          metadata: findMetadataPda(nftMint.publicKey)[0],
+          masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+          tokenProgram: TOKEN_PROGRAM_ID,
+          paymentTokenProgram: TOKEN_PROGRAM_ID,
+          tokenMetadataProgram: METADATA_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([userB])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("Unauthorized") || e.message.includes("ConstraintHasOne") || e.message.includes("constraint was violated"));
+    }
+  });
+
+  it("cannot claim if not creator", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const upfrontPercentage = 0;
+    const vestingDuration = 2;
+    const planSeed = "claim_auth";
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    await sleep(1100);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const wrongCreator = Keypair.generate();
+    const wrongCreatorToken = await createTokenAccount(paymentMint, wrongCreator.publicKey);
+
+    try {
+      await program.methods
+        .claimTokens()
+        .accounts({
+          plan: planPda,
+          userSubscription: userSubPda,
+          vault: vaultPda,
+          creatorToken: wrongCreatorToken,
+          creator: wrongCreator.publicKey,
+          paymentMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([wrongCreator])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("Unauthorized") || e.message.includes("constraint was violated"));
+    }
+  });
+
+  it("unauthorized vault access", async () => {
+    const paymentMint = await createMint(6);
+    const upfrontPercentage = 20;
+    const vestingDuration = 86400;
+    const planSeed = "vault_auth";
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+    const attacker = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(attacker.publicKey, LAMPORTS_PER_SOL)
+    );
+    const attackerToken = await createTokenAccount(paymentMint, attacker.publicKey);
+
+    const transferIx = createTransferInstruction(vaultPda, attackerToken, attacker.publicKey, 100n, [], TOKEN_PROGRAM_ID);
+    const tx = new anchor.web3.Transaction().add(transferIx);
+
+    try {
+      await provider.sendAndConfirm(tx, [attacker]);
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("0x1") || e.message.includes("Owner mismatch") || e.message.includes("signature"));
+    }
+  });
+
+  it("edge case zero upfront", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const upfrontPercentage = 0;
+    const vestingDuration = 2;
+    const planSeed = "zero_upfront_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+
+    // Claim immediately - should get 0
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    let userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.equal(userSub.claimedByCreatorAmount.toNumber(), 0, "Immediately after start, claimed should be 0");
+
+    // Ждем ДОЛЬШЕ чем vesting_duration (3 секунды вместо 2.1)
+    await sleep(3000);
+
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    userSub = await program.account.userSubscription.fetch(userSubPda);
+    
+    // Проверяем что claimed >= 99% от депозита (с допуском на погрешность)
+    const claimed = userSub.claimedByCreatorAmount.toNumber();
+    const expected = 1000 * 10**6;
+    assert.isAtLeast(claimed, expected * 0.99, "After vesting completes, should have ~100% claimed");
+    assert.isAtMost(claimed, expected, "Claimed should not exceed deposit");
+  });
+
+  it("edge case full upfront", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const upfrontPercentage = 100;
+    const vestingDuration = 2;
+    const planSeed = "full_upfront";
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert(userSub.claimedByCreatorAmount.eq(new BN(1000 * 10**6)));
+  });
+
+  it("edge case zero duration", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const upfrontPercentage = 0;
+    const vestingDuration = 0;
+    const planSeed = "zero_duration";
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, upfrontPercentage, vestingDuration, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert(userSub.claimedByCreatorAmount.eq(new BN(1000 * 10**6)));
+  });
+
+  it("buy subscription charges the selected tier", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "tiers_" + Math.random();
+
+    const tiers: Tier[] = [
+      { price: 100 * 10**6, vestingDuration: 86400, upfrontPercentage: 0, uri: "basic_uri" },
+      { price: 300 * 10**6, vestingDuration: 86400, upfrontPercentage: 10, uri: "pro_uri" },
+      { price: 900 * 10**6, vestingDuration: 86400, upfrontPercentage: 20, uri: "enterprise_uri" },
+    ];
+    const { planPda, vaultPda } = await createTieredPlan(paymentMint, tiers, planSeed);
+
+    const plan = await program.account.subscriptionPlan.fetch(planPda);
+    assert.equal(plan.tiers.length, 3);
+    assert.equal(plan.tiers[2].uri, "enterprise_uri");
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    // Tier index out of range is rejected
+    try {
+      await buySubscription(planPda, vaultPda, user, userToken, Keypair.generate(), 3, "NFT Name", "SYM", paymentMint, creatorToken);
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("InvalidTier"));
+    }
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 1, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.equal(userSub.tier, 1);
+    assert(userSub.totalDepositAmount.eq(new BN(300 * 10**6)));
+    assert(userSub.claimedByCreatorAmount.eq(new BN(30 * 10**6)));
+
+    const userBalance = await provider.connection.getTokenAccountBalance(userToken);
+    assert.equal(userBalance.value.amount, (700 * 10**6).toString());
+  });
+
+  it("change tier settles vested funds and restarts vesting", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "change_tier_" + Math.random();
+
+    const tiers: Tier[] = [
+      { price: 100 * 10**6, vestingDuration: 100000, upfrontPercentage: 0, uri: "basic_uri" },
+      { price: 300 * 10**6, vestingDuration: 100000, upfrontPercentage: 10, uri: "pro_uri" },
+    ];
+    const { planPda, vaultPda } = await createTieredPlan(paymentMint, tiers, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const changeTier = (tierIndex: number) =>
+      program.methods
+        .changeTier(tierIndex)
+        .accounts({
+          user: user.publicKey,
+          plan: planPda,
+          userSubscription: userSubPda,
+          nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+          paymentMint,
+          vault: vaultPda,
+          userToken,
+          creatorToken,
+          metadata: findMetadataPda(nftMint.publicKey)[0],
+          tokenProgram: TOKEN_PROGRAM_ID,
+          tokenMetadataProgram: METADATA_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+    // Upgrade: the unvested basic deposit is credited toward the pro price
+    await changeTier(1);
+
+    let userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.equal(userSub.tier, 1);
+    assert(userSub.totalDepositAmount.eq(new BN(300 * 10**6)));
+    assert(userSub.claimedByCreatorAmount.eq(new BN(30 * 10**6)));
+
+    let userBalance = Number((await provider.connection.getTokenAccountBalance(userToken)).value.amount);
+    assert.approximately(userBalance, 700 * 10**6, 10**6);
+
+    // Downgrade: the credit exceeds the basic price and the excess is refunded
+    await changeTier(0);
+
+    userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.equal(userSub.tier, 0);
+    assert(userSub.totalDepositAmount.eq(new BN(100 * 10**6)));
+
+    userBalance = Number((await provider.connection.getTokenAccountBalance(userToken)).value.amount);
+    const creatorBalance = Number((await provider.connection.getTokenAccountBalance(creatorToken)).value.amount);
+    const vaultBalance = Number((await provider.connection.getTokenAccountBalance(vaultPda)).value.amount);
+    assert.approximately(userBalance, 870 * 10**6, 10**6);
+    assert.equal(userBalance + creatorBalance + vaultBalance, 1000 * 10**6);
+
+    try {
+      await changeTier(0);
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("SameTier"));
+    }
+  });
+
+  it("subscription NFT cannot be transferred", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "soulbound_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 1000, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+    const buyer = Keypair.generate();
+    const buyerNftAta = await createTokenAccount(nftMint.publicKey, buyer.publicKey);
+
+    const transferIx = createTransferInstruction(nftAta, buyerNftAta, user.publicKey, 1n, [], TOKEN_PROGRAM_ID);
+    try {
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(transferIx), [user]);
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      // TokenError::AccountFrozen
+      assert(e.message.includes("0x11") || e.message.includes("frozen"));
+    }
+
+    const nftBalance = await provider.connection.getTokenAccountBalance(nftAta);
+    assert.equal(nftBalance.value.amount, "1");
+
+    // Closing thaws and burns the NFT as usual
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: findUserSubscriptionPda(planPda, user.publicKey)[0],
+        paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    assert.isNull(await provider.connection.getAccountInfo(nftAta));
+  });
+
+  it("transferable subscription follows the NFT holder", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "resale_" + Math.random();
+
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 100000, upfrontPercentage: 0, uri: "uri" }],
+      planSeed,
+      true
+    );
+
+    const seller = Keypair.generate();
+    const buyer = Keypair.generate();
+    for (const kp of [seller, buyer]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(kp.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
+
+    const sellerToken = await createTokenAccount(paymentMint, seller.publicKey);
+    const buyerToken = await createTokenAccount(paymentMint, buyer.publicKey);
+    await mintTokens(paymentMint, sellerToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, seller, sellerToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken, true);
+
+    const userSubPda = findMintSubscriptionPda(planPda, nftMint.publicKey)[0];
+    const sellerNftAta = getAssociatedTokenAddressSync(nftMint.publicKey, seller.publicKey);
+    const buyerNftAta = await createTokenAccount(nftMint.publicKey, buyer.publicKey);
+
+    // Secondary sale: the NFT is not frozen and moves freely
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        createTransferInstruction(sellerNftAta, buyerNftAta, seller.publicKey, 1n, [], TOKEN_PROGRAM_ID)
+      ),
+      [seller]
+    );
+
+    const closeAccounts = (user: Keypair, userToken: PublicKey, nftAta: PublicKey) => ({
+      user: user.publicKey,
+      plan: planPda,
+      userSubscription: userSubPda,
+      paymentMint,
+      vault: vaultPda,
+      userToken,
+      creatorToken,
+      nftMint: nftMint.publicKey,
+      nftAta,
+      metadata: findMetadataPda(nftMint.publicKey)[0],
+      masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+      tokenProgram: TOKEN_PROGRAM_ID,
+      paymentTokenProgram: TOKEN_PROGRAM_ID,
+      tokenMetadataProgram: METADATA_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    });
+
+    // The original buyer no longer holds the NFT and cannot claim the refund
+    try {
+      await program.methods
+        .closeSubscription(false)
+        .accounts(closeAccounts(seller, sellerToken, sellerNftAta))
+        .signers([seller])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("InvalidNftAmount"));
+    }
+
+    await program.methods
+      .closeSubscription(false)
+      .accounts(closeAccounts(buyer, buyerToken, buyerNftAta))
+      .signers([buyer])
+      .rpc();
+
+    const buyerBalance = Number((await provider.connection.getTokenAccountBalance(buyerToken)).value.amount);
+    assert.isAbove(buyerBalance, 1000 * 10**6 * 0.95, "Holder should receive the unvested balance");
+  });
+
+  it("subscription NFT is a verified member of the plan collection", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "collection_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 1000, planSeed);
+    const [collectionMint] = findCollectionMintPda(planPda);
+
+    // The collection NFT is held by the plan PDA
+    const collectionToken = getAssociatedTokenAddressSync(collectionMint, planPda, true);
+    const collectionBalance = await provider.connection.getTokenAccountBalance(collectionToken);
+    assert.equal(collectionBalance.value.amount, "1");
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const metadata = await provider.connection.getAccountInfo(findMetadataPda(nftMint.publicKey)[0]);
+    const collection = readMetadataCollection(metadata!.data);
+    assert.isNotNull(collection);
+    assert(collection!.key.equals(collectionMint));
+    assert.isTrue(collection!.verified);
+  });
+
+  it("assert active subscription gates on holder and unvested balance", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "gating_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 20, 100000, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const gateAccounts = (holder: PublicKey) => ({
+      plan: planPda,
+      userSubscription: userSubPda,
+      holder,
+      nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, holder),
+    });
+
+    const status = await program.methods
+      .assertActiveSubscription(null)
+      .accounts(gateAccounts(user.publicKey))
+      .view();
+    assert(status.holder.equals(user.publicKey));
+    assert(status.subscriptionMint.equals(nftMint.publicKey));
+    assert.equal(status.tier, 0);
+    assert.isAtMost(status.unvestedAmount.toNumber(), 800 * 10**6);
+    assert.isAbove(status.unvestedAmount.toNumber(), 790 * 10**6);
+
+    try {
+      await program.methods
+        .assertActiveSubscription(new BN(900 * 10**6))
+        .accounts(gateAccounts(user.publicKey))
+        .view();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("InsufficientUnvestedBalance"));
+    }
+
+    const stranger = Keypair.generate();
+    try {
+      await program.methods
+        .assertActiveSubscription(null)
+        .accounts(gateAccounts(stranger.publicKey))
+        .view();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("constraint was violated") || e.message.includes("AccountNotInitialized"));
+    }
+  });
+
+  it("expire subscription after the grace period", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "expiry_" + Math.random();
+
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 2, upfrontPercentage: 0, uri: "uri" }],
+      planSeed,
+      false,
+      1
+    );
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    let userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert(userSub.expiresAt.eq(userSub.startTime.add(new BN(2))));
+
+    const expire = () =>
+      program.methods
+        .expireSubscription()
+        .accounts({
+          plan: planPda,
+          userSubscription: userSubPda,
+          paymentMint,
+          vault: vaultPda,
+          creatorToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+    try {
+      await expire();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("SubscriptionNotExpired"));
+    }
+
+    // Paid period (2s) plus grace (1s)
+    await sleep(4000);
+    await expire();
+
+    userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.isFalse(userSub.isActive);
+    assert(userSub.claimedByCreatorAmount.eq(new BN(1000 * 10**6)));
+
+    const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(creatorBalance.value.amount, (1000 * 10**6).toString());
+
+    try {
+      await program.methods
+        .assertActiveSubscription(null)
+        .accounts({
+          plan: planPda,
+          userSubscription: userSubPda,
+          holder: user.publicKey,
+          nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+        })
+        .view();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("SubscriptionNotActive"));
+    }
+  });
+
+  it("renewal vests as its own tranche after the current period", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "tranches_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 100000, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 2000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+
+    await program.methods
+      .renewSubscription(new BN(1000 * 10**6))
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        vault: vaultPda,
+        userToken,
+        userSubscription: userSubPda,
+        nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    let userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.equal(userSub.tranches.length, 2);
+    assert(userSub.tranches[1].startTime.eq(userSub.tranches[0].startTime.add(new BN(100000))));
+    assert(userSub.tranches[1].duration.eq(new BN(100000)));
+    assert(userSub.expiresAt.eq(userSub.startTime.add(new BN(200000))));
+    assert(userSub.totalDepositAmount.eq(new BN(2000 * 10**6)));
+
+    await sleep(2000);
+
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    // Only the first tranche has started vesting: a few seconds out of 100000
+    userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.isAtMost(userSub.claimedByCreatorAmount.toNumber(), 1000 * 10**6 * 0.001);
+  });
+
+  it("auto-renewal pulls payments through the plan delegate up to the cap", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "auto_renew_" + Math.random();
+
+    // Period shorter than the renewal window, so a renewal is due right away
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 1000, planSeed, 100 * 10**6);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    await program.methods
+      .enableAutoRenewal(new BN(150 * 10**6))
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        nftAta,
+        userToken,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    // Anyone can crank the renewal
+    const processRenewal = () =>
+      program.methods
+        .processAutoRenewal()
+        .accounts({
+          plan: planPda,
+          userSubscription: userSubPda,
+          userToken,
+          nftAta,
+          paymentMint,
+          vault: vaultPda,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+    await processRenewal();
+
+    const userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.equal(userSub.tranches.length, 2);
+    assert(userSub.autoRenewSpent.eq(new BN(100 * 10**6)));
+    assert(userSub.totalDepositAmount.eq(new BN(200 * 10**6)));
+
+    const userBalance = await provider.connection.getTokenAccountBalance(userToken);
+    assert.equal(userBalance.value.amount, (800 * 10**6).toString());
+
+    // A second period would exceed the 150 cap
+    try {
+      await processRenewal();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("AutoRenewalCapExceeded"));
+    }
+
+    // The token-level approval backs one subscription; a second one on the same account is rejected
+    const secondMint = Keypair.generate();
+    await buySubscription(
+      planPda, vaultPda, user, userToken, secondMint, 0, "NFT Name", "SYM",
+      paymentMint, creatorToken, false, TOKEN_PROGRAM_ID, null, 1
+    );
+    try {
+      await program.methods
+        .enableAutoRenewal(new BN(150 * 10**6))
+        .accounts({
+          user: user.publicKey,
+          plan: planPda,
+          userSubscription: findUserSubscriptionPda(planPda, user.publicKey, 1)[0],
+          nftAta: getAssociatedTokenAddressSync(secondMint.publicKey, user.publicKey),
+          userToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("AutoRenewalTokenInUse"));
+    }
+
+    await program.methods
+      .disableAutoRenewal()
+      .accounts({
+        user: user.publicKey,
+        userSubscription: userSubPda,
+        userToken,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    try {
+      await processRenewal();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("AutoRenewalDisabled"));
+    }
+  });
+
+  it("token-2022 transfer-fee mint records what the vault received", async () => {
+    const paymentMint = await createTransferFeeMint(6, 100);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey, TOKEN_2022_PROGRAM_ID);
+    const planSeed = "token2022_" + Math.random();
+
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 100000, upfrontPercentage: 10, uri: "uri" }],
+      planSeed,
+      false,
+      0,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const userToken = await createTokenAccount(paymentMint, user.publicKey, TOKEN_2022_PROGRAM_ID);
+    await mintTokens(paymentMint, userToken, 2000 * 10**6, TOKEN_2022_PROGRAM_ID);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(
+      planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken,
+      false, TOKEN_2022_PROGRAM_ID
+    );
+
+    // 1% of the 900 sent to the vault is withheld by the mint
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    let userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert(userSub.totalDepositAmount.eq(new BN(991 * 10**6)));
+    let vaultBalance = await provider.connection.getTokenAccountBalance(vaultPda);
+    assert.equal(vaultBalance.value.amount, (891 * 10**6).toString());
+
+    await program.methods
+      .renewSubscription(new BN(1000 * 10**6))
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        vault: vaultPda,
+        userToken,
+        userSubscription: userSubPda,
+        nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+        paymentMint,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    // Access is priced on the amount paid, the deposit on the amount received
+    userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert(userSub.tranches[1].duration.eq(new BN(100000)));
+    assert(userSub.tranches[1].amount.eq(new BN(990 * 10**6)));
+    assert(userSub.totalDepositAmount.eq(new BN(1981 * 10**6)));
+    vaultBalance = await provider.connection.getTokenAccountBalance(vaultPda);
+    assert.equal(vaultBalance.value.amount, (1881 * 10**6).toString());
+  });
+
+  it("SOL plan splits, vests, claims and refunds in lamports", async () => {
+    const planSeed = "sol_" + Math.random();
+    const price = LAMPORTS_PER_SOL;
+    const { planPda, vaultPda } = await createTieredPlan(
+      null,
+      [{ price, vestingDuration: 1000, upfrontPercentage: 10, uri: "uri" }],
+      planSeed
+    );
+    const plan = await program.account.subscriptionPlan.fetch(planPda);
+    assert.isNull(plan.paymentMint);
+    const vaultRent = await provider.connection.getBalance(vaultPda);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+
+    const nftMint = Keypair.generate();
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+    const creatorBefore = await provider.connection.getBalance(payer.publicKey);
+
+    await program.methods
+      .buySubscription(0, "NFT Name", "SYM")
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        solVault: vaultPda,
+        creator: payer.publicKey,
+        userSubscription: userSubPda,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        ...collectionAccounts(planPda),
+      })
+      .signers([user, nftMint])
+      .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+      .rpc();
+
+    // Upfront goes straight to the creator wallet, the rest sits in the vault PDA
+    // (the creator wallet also pays the transaction fee here)
+    const creatorAfter = await provider.connection.getBalance(payer.publicKey);
+    assert.approximately(creatorAfter - creatorBefore, price / 10, 100_000);
+    assert.equal(await provider.connection.getBalance(vaultPda), vaultRent + (price * 9) / 10);
+
+    await sleep(2000);
+
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        solVault: vaultPda,
+        creator: payer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    let userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert(userSub.claimedByCreatorAmount.gt(new BN(price / 10)));
+
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        solVault: vaultPda,
+        creator: payer.publicKey,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    // Everything deposited has been paid out; only the vault's rent reserve remains
+    assert.equal(await provider.connection.getBalance(vaultPda), vaultRent);
+  });
+
+  it("claim many pays vested funds across subscriptions in one transfer", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "claim_many_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 1000, planSeed);
+
+    const userSubs: PublicKey[] = [];
+    for (let i = 0; i < 3; i++) {
+      const user = Keypair.generate();
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+      const userToken = await createTokenAccount(paymentMint, user.publicKey);
+      await mintTokens(paymentMint, userToken, 1000 * 10**6);
+      await buySubscription(planPda, vaultPda, user, userToken, Keypair.generate(), 0, "NFT Name", "SYM", paymentMint, creatorToken);
+      userSubs.push(findUserSubscriptionPda(planPda, user.publicKey)[0]);
+    }
+
+    await sleep(2000);
+
+    await program.methods
+      .claimMany()
+      .accounts({
+        plan: planPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(
+        // The first subscription is listed twice; it must only be paid once
+        [userSubs[0], ...userSubs].map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }))
+      )
+      .rpc();
+
+    let totalClaimed = new BN(0);
+    for (const userSubPda of userSubs) {
+      const userSub = await program.account.userSubscription.fetch(userSubPda);
+      assert(userSub.claimedByCreatorAmount.gtn(0));
+      totalClaimed = totalClaimed.add(userSub.claimedByCreatorAmount);
+    }
+    const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(creatorBalance.value.amount, totalClaimed.toString());
+  });
+
+  it("plan counters track deposits, claims and refunds", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "counters_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 10, 1000, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1500 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    await program.methods
+      .renewSubscription(new BN(500 * 10**6))
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        vault: vaultPda,
+        userToken,
+        userSubscription: userSubPda,
+        nftAta,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    let plan = await program.account.subscriptionPlan.fetch(planPda);
+    assert(plan.activeSubscribers.eqn(1));
+    assert(plan.totalDeposited.eq(new BN(1500 * 10**6)));
+    assert(plan.totalClaimedByCreator.eq(new BN(100 * 10**6)));
+    assert(plan.totalRefunded.eqn(0));
+
+    await sleep(2000);
+
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    // Every deposited token has left the vault either to the creator or back to the user
+    plan = await program.account.subscriptionPlan.fetch(planPda);
+    assert(plan.activeSubscribers.eqn(0));
+    assert(plan.totalDeposited.eq(plan.totalClaimedByCreator.add(plan.totalRefunded)));
+    const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(creatorBalance.value.amount, plan.totalClaimedByCreator.toString());
+    const vaultBalance = await provider.connection.getTokenAccountBalance(vaultPda);
+    assert.equal(vaultBalance.value.amount, "0");
+  });
+
+  it("paused plans block sales, sunset stops vesting and drained plans close", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "lifecycle_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 10, 1000, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const pauseSig = await program.methods
+      .pausePlan()
+      .accounts({ creator: payer.publicKey, plan: planPda })
+      .rpc({ commitment: "confirmed" });
+    const [paused] = await eventsOf(pauseSig);
+    assert.equal(paused.name, "planStatusChanged");
+    assert.isTrue(paused.data.paused);
+    assert.isFalse(paused.data.closed);
+
+    const nftMint = Keypair.generate();
+    try {
+      await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("PlanPaused"));
+    }
+
+    await program.methods
+      .resumePlan()
+      .accounts({ creator: payer.publicKey, plan: planPda })
+      .rpc();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    // Open subscriptions keep the plan alive
+    try {
+      await program.methods
+        .closePlan()
+        .accounts({
+          creator: payer.publicKey,
+          plan: planPda,
+          paymentMint,
+          vault: vaultPda,
+          creatorToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("PlanNotDrained"));
+    }
+
+    const now = Math.floor(Date.now() / 1000);
+    await program.methods
+      .sunsetPlan(new BN(now + 2))
+      .accounts({ creator: payer.publicKey, plan: planPda })
+      .rpc();
+
+    await sleep(5000);
+
+    // Vesting froze at the sunset, so the refund covers everything unvested at that moment
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const plan = await program.account.subscriptionPlan.fetch(planPda);
+    const vestedAfterUpfront = plan.totalClaimedByCreator.toNumber() - 100 * 10**6;
+    assert.isAtMost(vestedAfterUpfront, 900 * 10**6 * 10 / 1000);
+
+    await program.methods
+      .closePlan()
+      .accounts({
+        creator: payer.publicKey,
+        plan: planPda,
+        paymentMint,
+        vault: vaultPda,
+        creatorToken,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    assert.isNull(await provider.connection.getAccountInfo(planPda));
+    assert.isNull(await provider.connection.getAccountInfo(vaultPda));
+  });
+
+  it("expiry after a sunset leaves the unvested remainder to the subscriber", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 6, "sunset_expiry_" + Math.random());
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
     const userToken = await createTokenAccount(paymentMint, user.publicKey);
     await mintTokens(paymentMint, userToken, 1000 * 10**6);
 
     const nftMint = Keypair.generate();
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 1000 * 10**6, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
-
-    await sleep(1100);
-
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
     const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
-    const wrongCreator = Keypair.generate();
-    const wrongCreatorToken = await createTokenAccount(paymentMint, wrongCreator.publicKey);
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    await program.methods
+      .sunsetPlan(new BN(Math.floor(Date.now() / 1000) + 1))
+      .accounts({ creator: payer.publicKey, plan: planPda })
+      .rpc();
 
+    // Past the paid period, but vesting stopped at the sunset with most of the deposit unvested
+    await sleep(8000);
     try {
       await program.methods
-        .claimTokens()
+        .expireSubscription()
         .accounts({
           plan: planPda,
           userSubscription: userSubPda,
-          vault: vaultPda,
-          creatorToken: wrongCreatorToken,
-          creator: wrongCreator.publicKey,
           paymentMint,
+          vault: vaultPda,
+          creatorToken,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
-        .signers([wrongCreator])
         .rpc();
       assert.fail("Should have failed");
     } catch (e: any) {
-      assert(e.message.includes("Unauthorized") || e.message.includes("constraint was violated"));
+      assert(e.message.includes("UnvestedFundsRemain"));
     }
+
+    // The subscriber can still close for the remainder, which drains the plan
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const userBalance = Number((await provider.connection.getTokenAccountBalance(userToken)).value.amount);
+    assert.isAbove(userBalance, 500 * 10**6);
+
+    await program.methods
+      .closePlan()
+      .accounts({
+        creator: payer.publicKey,
+        plan: planPda,
+        paymentMint,
+        vault: vaultPda,
+        creatorToken,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    assert.isNull(await provider.connection.getAccountInfo(planPda));
   });
 
-  it("unauthorized vault access", async () => {
+  it("protocol fee is taken from the upfront share and creator claims", async () => {
     const paymentMint = await createMint(6);
-    const nftCollection = Keypair.generate().publicKey;
-    const upfrontPercentage = 20;
-    const vestingDuration = 86400;
-    const planSeed = "vault_auth";
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const treasury = Keypair.generate();
+    const treasuryToken = await createTokenAccount(paymentMint, treasury.publicKey);
+    const planSeed = "protocol_fee_" + Math.random();
 
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createPlan(paymentMint, 10, 2, planSeed);
 
-    const attacker = Keypair.generate();
+    const user = Keypair.generate();
     await provider.connection.confirmTransaction(
-      await provider.connection.requestAirdrop(attacker.publicKey, LAMPORTS_PER_SOL)
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
     );
-    const attackerToken = await createTokenAccount(paymentMint, attacker.publicKey);
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
 
-    const transferIx = createTransferInstruction(vaultPda, attackerToken, attacker.publicKey, 100n, [], TOKEN_PROGRAM_ID);
-    const tx = new anchor.web3.Transaction().add(transferIx);
+    // Only the upgrade authority may set the fee
+    const stranger = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(stranger.publicKey, LAMPORTS_PER_SOL)
+    );
+    try {
+      await program.methods
+        .updateProtocolConfig(500, stranger.publicKey)
+        .accounts({ admin: stranger.publicKey, programData: programDataPda })
+        .signers([stranger])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("Unauthorized"));
+    }
 
+    await setProtocolFee(500, treasury.publicKey);
     try {
-      await provider.sendAndConfirm(tx, [attacker]);
+      const nftMint = Keypair.generate();
+      await buySubscription(
+        planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken,
+        false, TOKEN_PROGRAM_ID, treasuryToken
+      );
+
+      // 5% of the 100 token upfront share
+      let treasuryBalance = await provider.connection.getTokenAccountBalance(treasuryToken);
+      assert.equal(treasuryBalance.value.amount, (5 * 10**6).toString());
+      let creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+      assert.equal(creatorBalance.value.amount, (95 * 10**6).toString());
+
+      await sleep(3000);
+
+      await program.methods
+        .claimTokens()
+        .accounts({
+          plan: planPda,
+          userSubscription: findUserSubscriptionPda(planPda, user.publicKey)[0],
+          vault: vaultPda,
+          creatorToken,
+          protocolFee: { treasuryToken },
+          creator: payer.publicKey,
+          paymentMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+      // The fully vested 900 tokens pay another 45 to the treasury
+      treasuryBalance = await provider.connection.getTokenAccountBalance(treasuryToken);
+      assert.equal(treasuryBalance.value.amount, (50 * 10**6).toString());
+      creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+      assert.equal(creatorBalance.value.amount, (950 * 10**6).toString());
+    } finally {
+      await setProtocolFee(0, payer.publicKey);
+    }
+  });
+
+  it("protocol fee is taken from expiry payouts to the creator", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const treasury = Keypair.generate();
+    const treasuryToken = await createTokenAccount(paymentMint, treasury.publicKey);
+    const planSeed = "protocol_fee_expiry_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 0, 2, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    await setProtocolFee(500, treasury.publicKey);
+    try {
+      const nftMint = Keypair.generate();
+      await buySubscription(
+        planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken,
+        false, TOKEN_PROGRAM_ID, treasuryToken
+      );
+
+      await sleep(3000);
+
+      await program.methods
+        .expireSubscription()
+        .accounts({
+          plan: planPda,
+          userSubscription: findUserSubscriptionPda(planPda, user.publicKey)[0],
+          paymentMint,
+          vault: vaultPda,
+          protocolFee: { treasuryToken },
+          creatorToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+      // 5% of the fully vested 1000 tokens
+      const treasuryBalance = await provider.connection.getTokenAccountBalance(treasuryToken);
+      assert.equal(treasuryBalance.value.amount, (50 * 10**6).toString());
+      const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+      assert.equal(creatorBalance.value.amount, (950 * 10**6).toString());
+    } finally {
+      await setProtocolFee(0, payer.publicKey);
+    }
+  });
+
+  it("purchases, renewals and closes emit events with the computed amounts", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "events_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 10, 1000, planSeed);
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1500 * 10**6);
+
+    const nftMint = Keypair.generate();
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    // buySubscription does not expose its signature, so listen for the purchase instead
+    let purchased: any = null;
+    const listener = program.addEventListener("subscriptionPurchased", (event) => {
+      if (event.plan.equals(planPda)) purchased = event;
+    });
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+    await sleep(1000);
+    await program.removeEventListener(listener);
+
+    assert.isNotNull(purchased);
+    assert(purchased.userSubscription.equals(userSubPda));
+    assert(purchased.deposit.eq(new BN(1000 * 10**6)));
+    assert(purchased.upfront.eq(new BN(100 * 10**6)));
+    assert(purchased.protocolFee.eqn(0));
+
+    const renewSig = await program.methods
+      .renewSubscription(new BN(500 * 10**6))
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        vault: vaultPda,
+        userToken,
+        userSubscription: userSubPda,
+        nftAta,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc({ commitment: "confirmed" });
+    const [renewed] = await eventsOf(renewSig);
+    assert.equal(renewed.name, "renewed");
+    assert(renewed.data.amount.eq(new BN(500 * 10**6)));
+    assert.isFalse(renewed.data.autoRenewal);
+
+    const closeSig = await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc({ commitment: "confirmed" });
+    const [closed] = await eventsOf(closeSig);
+    assert.equal(closed.name, "subscriptionClosed");
+    assert(closed.data.subscriber.equals(user.publicKey));
+    // Everything deposited is either vested or refunded
+    assert(closed.data.vested.add(closed.data.refund).eq(new BN(1500 * 10**6)));
+    assert(closed.data.paidToCreator.eq(closed.data.vested.sub(new BN(100 * 10**6))));
+  });
+
+  it("stepped schedule vests nothing until the first installment", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "stepped_" + Math.random();
+
+    // Three monthly installments after a 10% upfront share
+    const month = 30 * 86400;
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 3 * month, upfrontPercentage: 10, uri: "uri" }],
+      planSeed,
+      false,
+      0,
+      TOKEN_PROGRAM_ID,
+      { stepped: { step: new BN(month) } }
+    );
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+
+    await sleep(2000);
+
+    const status = await program.methods
+      .assertActiveSubscription(null)
+      .accounts({
+        holder: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+      })
+      .view();
+    assert(status.unvestedAmount.eq(new BN(900 * 10**6)));
+
+    // Invalid schedules are rejected up front
+    try {
+      await createTieredPlan(
+        paymentMint,
+        [{ price: 1000 * 10**6, vestingDuration: month, upfrontPercentage: 10, uri: "uri" }],
+        "stepped_zero_" + Math.random(),
+        false,
+        0,
+        TOKEN_PROGRAM_ID,
+        { stepped: { step: new BN(0) } }
+      );
       assert.fail("Should have failed");
     } catch (e: any) {
-      assert(e.message.includes("0x1") || e.message.includes("Owner mismatch") || e.message.includes("signature"));
+      assert(e.message.includes("InvalidVestingSchedule"));
+    }
+
+    // A plan cliff would stack with a schedule's own cliff, so it needs a linear schedule
+    try {
+      await createTieredPlan(
+        paymentMint,
+        [{ price: 1000 * 10**6, vestingDuration: month, upfrontPercentage: 10, uri: "uri" }],
+        "stepped_cliff_" + Math.random(),
+        false,
+        0,
+        TOKEN_PROGRAM_ID,
+        { cliffLinear: { cliff: new BN(100) } },
+        100
+      );
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("InvalidVestingSchedule"));
     }
   });
 
-  it("edge case zero upfront", async () => {
+  it("cliff locks everything but the upfront share", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "cliff_" + Math.random();
+
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 1000, upfrontPercentage: 10, uri: "uri" }],
+      planSeed,
+      false,
+      0,
+      TOKEN_PROGRAM_ID,
+      { linear: {} },
+      500
+    );
+
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
+    const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    await sleep(2000);
+
+    // Inside the cliff the creator has nothing to claim beyond the upfront share
+    await program.methods
+      .claimTokens()
+      .accounts({
+        plan: planPda,
+        userSubscription: userSubPda,
+        vault: vaultPda,
+        creatorToken,
+        creator: payer.publicKey,
+        paymentMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+    let creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(creatorBalance.value.amount, (100 * 10**6).toString());
+
+    // ...and closing refunds the whole remainder
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
+        paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+        tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+    const userBalance = await provider.connection.getTokenAccountBalance(userToken);
+    assert.equal(userBalance.value.amount, (900 * 10**6).toString());
+    creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(creatorBalance.value.amount, (100 * 10**6).toString());
+  });
+
+  it("changing tiers doesn't restart the plan cliff", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    const nftCollection = Keypair.generate().publicKey;
-    const upfrontPercentage = 0;
-    const vestingDuration = 2;
-    const planSeed = "zero_upfront_" + Math.random();
-
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const tiers: Tier[] = [
+      { price: 1000 * 10**6, vestingDuration: 1000, upfrontPercentage: 0, uri: "basic_uri" },
+      { price: 1000 * 10**6, vestingDuration: 1000, upfrontPercentage: 0, uri: "pro_uri" },
+    ];
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      tiers,
+      "cliff_tier_" + Math.random(),
+      false,
+      0,
+      TOKEN_PROGRAM_ID,
+      { linear: {} },
+      3
+    );
 
     const user = Keypair.generate();
     await provider.connection.confirmTransaction(
       await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
     );
-
     const userToken = await createTokenAccount(paymentMint, user.publicKey);
     await mintTokens(paymentMint, userToken, 1000 * 10**6);
 
     const nftMint = Keypair.generate();
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 1000 * 10**6, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
-
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
     const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const cliffEnd = (await program.account.userSubscription.fetch(userSubPda)).cliffEnd.toNumber();
 
-    // Claim immediately - should get 0
+    await sleep(5000);
     await program.methods
-      .claimTokens()
+      .changeTier(1)
       .accounts({
+        user: user.publicKey,
         plan: planPda,
         userSubscription: userSubPda,
+        nftAta: getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey),
+        paymentMint,
         vault: vaultPda,
+        userToken,
         creatorToken,
-        creator: payer.publicKey,
-        paymentMint,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
         tokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
       })
+      .signers([user])
       .rpc();
+    assert.equal((await program.account.userSubscription.fetch(userSubPda)).cliffEnd.toNumber(), cliffEnd);
 
-    let userSub = await program.account.userSubscription.fetch(userSubPda);
-    assert.equal(userSub.claimedByCreatorAmount.toNumber(), 0, "Immediately after start, claimed should be 0");
-
-    // Ждем ДОЛЬШЕ чем vesting_duration (3 секунды вместо 2.1)
-    await sleep(3000);
-
+    // The cliff already passed, so the new tier's tranche vests right away
+    await sleep(2000);
+    const before = Number((await provider.connection.getTokenAccountBalance(creatorToken)).value.amount);
     await program.methods
       .claimTokens()
       .accounts({
@@ -796,94 +3048,454 @@ it("query unvested balance", async () => {
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .rpc();
-
-    userSub = await program.account.userSubscription.fetch(userSubPda);
-    
-    // Проверяем что claimed >= 99% от депозита (с допуском на погрешность)
-    const claimed = userSub.claimedByCreatorAmount.toNumber();
-    const expected = 1000 * 10**6;
-    assert.isAtLeast(claimed, expected * 0.99, "After vesting completes, should have ~100% claimed");
-    assert.isAtMost(claimed, expected, "Claimed should not exceed deposit");
+    const after = Number((await provider.connection.getTokenAccountBalance(creatorToken)).value.amount);
+    assert.isAbove(after, before);
   });
 
-  it("edge case full upfront", async () => {
+  it("free trial converts to a paid subscription once per wallet", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    const nftCollection = Keypair.generate().publicKey;
-    const upfrontPercentage = 100;
-    const vestingDuration = 2;
-    const planSeed = "full_upfront";
+    const planSeed = "trial_" + Math.random();
 
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 1000, upfrontPercentage: 10, uri: "uri" }],
+      planSeed,
+      false,
+      0,
+      TOKEN_PROGRAM_ID,
+      { linear: {} },
+      0,
+      600
+    );
 
     const user = Keypair.generate();
     await provider.connection.confirmTransaction(
       await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
     );
-
     const userToken = await createTokenAccount(paymentMint, user.publicKey);
     await mintTokens(paymentMint, userToken, 1000 * 10**6);
 
-    const nftMint = Keypair.generate();
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 1000 * 10**6, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
+    const startTrial = (nftMint: Keypair) =>
+      program.methods
+        .startTrial(0, "Trial", "TRL")
+        .accounts({
+          user: user.publicKey,
+          plan: planPda,
+          nftMint: nftMint.publicKey,
+          metadata: findMetadataPda(nftMint.publicKey)[0],
+          masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          tokenMetadataProgram: METADATA_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          ...collectionAccounts(planPda),
+        })
+        .signers([user, nftMint])
+        .preInstructions([anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+        .rpc();
 
+    const nftMint = Keypair.generate();
+    await startTrial(nftMint);
     const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    let userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.isTrue(userSub.trial);
+    assert(userSub.totalDepositAmount.eqn(0));
+
+    // Trials can't be renewed directly
+    try {
+      await program.methods
+        .renewSubscription(new BN(100 * 10**6))
+        .accounts({
+          user: user.publicKey,
+          plan: planPda,
+          vault: vaultPda,
+          userToken,
+          userSubscription: userSubPda,
+          nftAta,
+          paymentMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("TrialNotConverted"));
+    }
 
     await program.methods
-      .claimTokens()
+      .convertTrial()
       .accounts({
+        user: user.publicKey,
         plan: planPda,
         userSubscription: userSubPda,
+        nftAta,
+        paymentMint,
         vault: vaultPda,
+        userToken,
         creatorToken,
-        creator: payer.publicKey,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.isFalse(userSub.trial);
+    assert(userSub.subscriptionMint.equals(nftMint.publicKey));
+    assert(userSub.totalDepositAmount.eq(new BN(1000 * 10**6)));
+    const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(creatorBalance.value.amount, (100 * 10**6).toString());
+    const vaultBalance = await provider.connection.getTokenAccountBalance(vaultPda);
+    assert.equal(vaultBalance.value.amount, (900 * 10**6).toString());
+
+    // Close, then try a second trial: the trial record PDA already exists
+    await program.methods
+      .closeSubscription(false)
+      .accounts({
+        user: user.publicKey,
+        plan: planPda,
+        userSubscription: userSubPda,
         paymentMint,
+        vault: vaultPda,
+        userToken,
+        creatorToken,
+        nftMint: nftMint.publicKey,
+        nftAta,
+        metadata: findMetadataPda(nftMint.publicKey)[0],
+        masterEdition: findMasterEditionPda(nftMint.publicKey)[0],
         tokenProgram: TOKEN_PROGRAM_ID,
+        paymentTokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
       })
+      .signers([user])
       .rpc();
 
-    const userSub = await program.account.userSubscription.fetch(userSubPda);
-    assert(userSub.claimedByCreatorAmount.eq(new BN(1000 * 10**6)));
+    try {
+      await startTrial(Keypair.generate());
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("already in use"));
+    }
   });
 
-  it("edge case zero duration", async () => {
+  it("recovers a subscription whose NFT left the subscriber's ATA", async () => {
     const paymentMint = await createMint(6);
     const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
-    const nftCollection = Keypair.generate().publicKey;
-    const upfrontPercentage = 0;
-    const vestingDuration = 0;
-    const planSeed = "zero_duration";
+    const planSeed = "recover_" + Math.random();
 
-    const { planPda, vaultPda } = await createPlan(paymentMint, nftCollection, upfrontPercentage, vestingDuration, planSeed);
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 1000, upfrontPercentage: 0, uri: "uri" }],
+      planSeed,
+      true
+    );
 
     const user = Keypair.generate();
     await provider.connection.confirmTransaction(
       await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
     );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken, true);
+    const userSubPda = findMintSubscriptionPda(planPda, nftMint.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    // Park the NFT in a plain (non-associated) token account
+    const holding = Keypair.generate();
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer.publicKey,
+          newAccountPubkey: holding.publicKey,
+          lamports: await provider.connection.getMinimumBalanceForRentExemption(ACCOUNT_SIZE),
+          space: ACCOUNT_SIZE,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeAccountInstruction(holding.publicKey, nftMint.publicKey, user.publicKey),
+        createTransferInstruction(nftAta, holding.publicKey, user.publicKey, 1n, [], TOKEN_PROGRAM_ID)
+      ),
+      [holding, user]
+    );
+
+    const recoverAccounts = {
+      user: user.publicKey,
+      plan: planPda,
+      userSubscription: userSubPda,
+      nftMint: nftMint.publicKey,
+      paymentMint,
+      vault: vaultPda,
+      userToken,
+      creatorToken,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      paymentTokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    };
+
+    // Without a proof of holding the NFT still exists, so there is nothing to recover
+    try {
+      await program.methods
+        .recoverSubscription()
+        .accounts({ ...recoverAccounts, nftHolding: null })
+        .signers([user])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("NftNotLost"));
+    }
+
+    await program.methods
+      .recoverSubscription()
+      .accounts({ ...recoverAccounts, nftHolding: holding.publicKey })
+      .signers([user])
+      .rpc();
+
+    const mintInfo = await provider.connection.getParsedAccountInfo(nftMint.publicKey);
+    assert.equal((mintInfo.value!.data as any).parsed.info.supply, "0");
+    assert.isNull(await provider.connection.getAccountInfo(userSubPda));
+
+    // Nearly the whole deposit came back; the creator got what vested meanwhile
+    const userBalance = await provider.connection.getTokenAccountBalance(userToken);
+    const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(
+      BigInt(userBalance.value.amount) + BigInt(creatorBalance.value.amount),
+      BigInt(1000 * 10**6)
+    );
+    assert.isAbove(Number(userBalance.value.amount), 950 * 10**6);
+  });
+
+  it("lets the original buyer recover a subscription whose NFT was burned", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "recover_burn_" + Math.random();
+
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 1000, upfrontPercentage: 0, uri: "uri" }],
+      planSeed,
+      true
+    );
 
+    const user = Keypair.generate();
+    const holder = Keypair.generate();
+    for (const wallet of [user, holder]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(wallet.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
     const userToken = await createTokenAccount(paymentMint, user.publicKey);
     await mintTokens(paymentMint, userToken, 1000 * 10**6);
 
     const nftMint = Keypair.generate();
-    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 1000 * 10**6, "NFT Name", "SYM", "uri", paymentMint, creatorToken);
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken, true);
+    const userSubPda = findMintSubscriptionPda(planPda, nftMint.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+    const holderAta = getAssociatedTokenAddressSync(nftMint.publicKey, holder.publicKey);
+
+    // The NFT changes hands and the new holder burns it
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        createAssociatedTokenAccountInstruction(payer.publicKey, holderAta, holder.publicKey, nftMint.publicKey),
+        createTransferInstruction(nftAta, holderAta, user.publicKey, 1n, [], TOKEN_PROGRAM_ID),
+        createBurnInstruction(holderAta, nftMint.publicKey, holder.publicKey, 1n, [], TOKEN_PROGRAM_ID)
+      ),
+      [user, holder]
+    );
+
+    const recoverAccounts = {
+      plan: planPda,
+      userSubscription: userSubPda,
+      nftMint: nftMint.publicKey,
+      nftHolding: null,
+      paymentMint,
+      vault: vaultPda,
+      creatorToken,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      paymentTokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    };
+
+    // The last holder never bought the subscription, so the deposit isn't theirs
+    try {
+      await program.methods
+        .recoverSubscription()
+        .accounts({ ...recoverAccounts, user: holder.publicKey, userToken: null, subscriberIndex: null })
+        .signers([holder])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("NotOriginalSubscriber"));
+    }
+
+    await program.methods
+      .recoverSubscription()
+      .accounts({
+        ...recoverAccounts,
+        user: user.publicKey,
+        userToken,
+        subscriberIndex: findSubscriberIndexPda(planPda, user.publicKey)[0],
+      })
+      .signers([user])
+      .rpc();
+
+    assert.isNull(await provider.connection.getAccountInfo(userSubPda));
+    const userBalance = await provider.connection.getTokenAccountBalance(userToken);
+    const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(
+      BigInt(userBalance.value.amount) + BigInt(creatorBalance.value.amount),
+      BigInt(1000 * 10**6)
+    );
+    assert.isAbove(Number(userBalance.value.amount), 950 * 10**6);
+  });
+
+  it("creator cancels a subscription with a pro-rata refund", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "cancel_" + Math.random();
+
+    const { planPda, vaultPda } = await createPlan(paymentMint, 10, 1000, planSeed);
 
+    const user = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(user.publicKey, 10 * LAMPORTS_PER_SOL)
+    );
+    const userToken = await createTokenAccount(paymentMint, user.publicKey);
+    await mintTokens(paymentMint, userToken, 1000 * 10**6);
+
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, user, userToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken);
     const userSubPda = findUserSubscriptionPda(planPda, user.publicKey)[0];
+    const nftAta = getAssociatedTokenAddressSync(nftMint.publicKey, user.publicKey);
+
+    await sleep(2000);
+
+    const cancelAccounts = {
+      creator: payer.publicKey,
+      plan: planPda,
+      userSubscription: userSubPda,
+      subscriber: user.publicKey,
+      nftMint: nftMint.publicKey,
+      nftAta,
+      metadata: findMetadataPda(nftMint.publicKey)[0],
+      paymentMint,
+      vault: vaultPda,
+      subscriberToken: userToken,
+      creatorToken,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenMetadataProgram: METADATA_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    };
+
+    // Only the plan creator may cancel
+    const stranger = Keypair.generate();
+    try {
+      await program.methods
+        .creatorCancelSubscription()
+        .accounts({ ...cancelAccounts, creator: stranger.publicKey })
+        .signers([stranger])
+        .rpc();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("Unauthorized") || e.message.includes("ConstraintSeeds"));
+    }
+
+    await program.methods.creatorCancelSubscription().accounts(cancelAccounts).rpc();
+
+    const userSub = await program.account.userSubscription.fetch(userSubPda);
+    assert.isFalse(userSub.isActive);
+    assert(userSub.claimedByCreatorAmount.add(userSub.refundTokenAmount).eq(new BN(1000 * 10**6)));
+
+    const userBalance = await provider.connection.getTokenAccountBalance(userToken);
+    assert.equal(userBalance.value.amount, userSub.refundTokenAmount.toString());
+    const creatorBalance = await provider.connection.getTokenAccountBalance(creatorToken);
+    assert.equal(creatorBalance.value.amount, userSub.claimedByCreatorAmount.toString());
+    const vaultBalance = await provider.connection.getTokenAccountBalance(vaultPda);
+    assert.equal(vaultBalance.value.amount, "0");
+
+    // The NFT stays frozen and no longer passes the gate
+    const nftAccount = await provider.connection.getParsedAccountInfo(nftAta);
+    assert.equal((nftAccount.value!.data as any).parsed.info.state, "frozen");
+    try {
+      await program.methods
+        .assertActiveSubscription(null)
+        .accounts({ holder: user.publicKey, plan: planPda, userSubscription: userSubPda, nftAta })
+        .view();
+      assert.fail("Should have failed");
+    } catch (e: any) {
+      assert(e.message.includes("SubscriptionNotActive"));
+    }
+  });
+
+  it("creator cancel renames NFTs on transferable plans", async () => {
+    const paymentMint = await createMint(6);
+    const creatorToken = await createTokenAccount(paymentMint, payer.publicKey);
+    const planSeed = "cancel_transferable_" + Math.random();
+
+    const { planPda, vaultPda } = await createTieredPlan(
+      paymentMint,
+      [{ price: 1000 * 10**6, vestingDuration: 1000, upfrontPercentage: 10, uri: "uri" }],
+      planSeed,
+      true
+    );
+
+    const seller = Keypair.generate();
+    const buyer = Keypair.generate();
+    for (const kp of [seller, buyer]) {
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(kp.publicKey, 10 * LAMPORTS_PER_SOL)
+      );
+    }
+    const sellerToken = await createTokenAccount(paymentMint, seller.publicKey);
+    const buyerToken = await createTokenAccount(paymentMint, buyer.publicKey);
+    await mintTokens(paymentMint, sellerToken, 1000 * 10**6);
 
+    const nftMint = Keypair.generate();
+    await buySubscription(planPda, vaultPda, seller, sellerToken, nftMint, 0, "NFT Name", "SYM", paymentMint, creatorToken, true);
+    const userSubPda = findMintSubscriptionPda(planPda, nftMint.publicKey)[0];
+    const sellerNftAta = getAssociatedTokenAddressSync(nftMint.publicKey, seller.publicKey);
+    const buyerNftAta = await createTokenAccount(nftMint.publicKey, buyer.publicKey);
+
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        createTransferInstruction(sellerNftAta, buyerNftAta, seller.publicKey, 1n, [], TOKEN_PROGRAM_ID)
+      ),
+      [seller]
+    );
+
+    const metadataPda = findMetadataPda(nftMint.publicKey)[0];
     await program.methods
-      .claimTokens()
+      .creatorCancelSubscription()
       .accounts({
+        creator: payer.publicKey,
         plan: planPda,
         userSubscription: userSubPda,
+        subscriber: buyer.publicKey,
+        nftMint: nftMint.publicKey,
+        nftAta: buyerNftAta,
+        metadata: metadataPda,
+        paymentMint,
         vault: vaultPda,
+        subscriberToken: buyerToken,
         creatorToken,
-        creator: payer.publicKey,
-        paymentMint,
         tokenProgram: TOKEN_PROGRAM_ID,
+        tokenMetadataProgram: METADATA_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
       })
       .rpc();
 
-    const userSub = await program.account.userSubscription.fetch(userSubPda);
-    assert(userSub.claimedByCreatorAmount.eq(new BN(1000 * 10**6)));
+    // Transferable NFTs are never frozen, so the name marks the cancellation
+    const nftAccount = await provider.connection.getParsedAccountInfo(buyerNftAta);
+    assert.equal((nftAccount.value!.data as any).parsed.info.state, "initialized");
+    // Metadata layout: key (1), update authority (32), mint (32), then the length-prefixed name
+    const metadata = (await provider.connection.getAccountInfo(metadataPda))!.data;
+    const nameLen = metadata.readUInt32LE(65);
+    const name = metadata.subarray(69, 69 + nameLen).toString().replace(/\0/g, "");
+    assert.equal(name, "Cancelled: NFT Name");
   });
-
-});
\ No newline at end of file
+});